@@ -1,260 +1,3157 @@
 use crate::db;
 
 use crate::db::IdxAlias;
-use camino::Utf8PathBuf;
+use crate::db::IdxAlias::Idx;
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{FixedOffset, Local, TimeZone, Utc};
 use path_absolutize::*;
+use regex::Regex;
+use rusqlite::Connection;
 use std::cmp;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+use unicode_width::UnicodeWidthStr;
+
+const NORMSEP_KEY: &str = "QCD_RS_NORMALIZE_SEPARATORS";
+const RESOLVER_KEY: &str = "QCD_RS_RESOLVER";
+const RESOLVERADD_KEY: &str = "QCD_RS_RESOLVER_ADD";
+const ONMISSING_KEY: &str = "QCD_RS_ON_MISSING";
+const SEARCHPATH_KEY: &str = "QCD_RS_DB_SEARCH_PATH";
+const PROMPTGLYPH_KEY: &str = "QCD_RS_PROMPT_GLYPH";
+const PROMPTGLYPH: &str = "\u{21e1}";
+const STATTIMEOUT_MS: u64 = 500;
+
+// Exit codes. Shell wrappers change directory on EXIT_OK for navigation commands.
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_ERROR: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_NOTFOUND: i32 = 3;
+
+/// Escapes a string for usage inside a JSON string literal.
+fn json_escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res
+} // json_escape
+
+/// Category of an error, reported as 'kind' of JSON errors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    General,
+    NoDatabase,
+    NotFound,
+    Path,
+    SelfTest,
+    Session,
+    Stack,
+    Usage,
+}
+
+impl ErrorKind {
+    /// Name of kind in JSON errors
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::General => "general",
+            ErrorKind::NoDatabase => "no_database",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::Path => "path",
+            ErrorKind::SelfTest => "self_test",
+            ErrorKind::Session => "session",
+            ErrorKind::Stack => "stack",
+            ErrorKind::Usage => "usage",
+        }
+    }
+
+    /// Exit code of errors of this kind
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ErrorKind::Usage | ErrorKind::Session => EXIT_USAGE,
+            ErrorKind::NotFound => EXIT_NOTFOUND,
+            _ => EXIT_ERROR,
+        }
+    }
+} // impl ErrorKind
+
+/// Set by main if errors are to be printed as JSON objects
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+/// Makes print_error write JSON objects.
+pub fn use_json_errors() {
+    JSON_ERRORS.store(true, Ordering::Relaxed);
+} // use_json_errors
+
+fn json_errors() -> bool {
+    JSON_ERRORS.load(Ordering::Relaxed)
+} // json_errors
+
+/// Prints an error message of category kind.
+///
+/// If use_json_errors was called a JSON object is written
+/// to stderr, otherwise the human readable message goes to stdout.
+pub fn print_error(kind: ErrorKind, msg: &str) {
+    if json_errors() {
+        eprintln!(
+            "{{\"error\":\"{}\",\"kind\":\"{}\"}}",
+            json_escape(msg),
+            kind.as_str()
+        );
+    } else {
+        println!("ERROR: {msg}");
+    }
+} // print_error
+
+/// Prints an error message of category kind and exits with the
+/// corresponding exit code.
+pub fn exit_with_error(kind: ErrorKind, msg: &str) -> ! {
+    print_error(kind, msg);
+    process::exit(kind.exit_code());
+} // exit_with_error
+
+/// Prints an error returned from db and exits. Failed searches are
+/// reported as NotFound, other errors as kind.
+fn exit_with_db_error(kind: ErrorKind, err: &str) -> ! {
+    if db::is_not_found(err) {
+        exit_with_error(ErrorKind::NotFound, err);
+    }
+    exit_with_error(kind, err);
+} // exit_with_db_error
 
 /// Unwraps 'what' if Ok, otherwise prints containing
 /// error message and exits.
 fn check_and_unwrap<T>(what: Result<T, String>) -> T {
     match what {
-        Err(e) => {
-            println!("ERROR: {e}");
-            process::exit(1);
+        Err(e) => exit_with_db_error(ErrorKind::General, &e),
+        Ok(s) => s,
+    }
+} // check_and_unwrap
+
+/// Normalizes Windows extended-length (`\\?\C:\...`) and UNC
+/// (`\\server\share\...`) paths, which are absolute already.
+/// Returns None for other paths.
+///
+/// Extended-length paths are taken literally (Windows does not interpret
+/// '/', '.' or '..' in them), only a trailing separator gets removed.
+/// For UNC paths '/' becomes '\\', duplicate separators as well as '.'
+/// components are removed and '..' is resolved without leaving the share.
+fn normalize_windows_prefixed(path: &str) -> Option<String> {
+    if path.starts_with(r"\\?\") || path.starts_with(r"\\.\") {
+        let trimmed = path.trim_end_matches('\\');
+        if trimmed.len() > 4 && !trimmed.ends_with(':') {
+            return Some(trimmed.to_string());
+        }
+        return Some(path.to_string());
+    }
+
+    let unified = path.replace('/', "\\");
+    let rest = unified.strip_prefix(r"\\")?;
+    let mut components = Vec::<&str>::new();
+    for c in rest.split('\\') {
+        match c {
+            "" | "." => {}
+            // Server and share cannot be left
+            ".." => {
+                if components.len() > 2 {
+                    components.pop();
+                }
+            }
+            _ => components.push(c),
+        }
+    }
+    if components.len() < 2 {
+        return None;
+    }
+    Some(format!(r"\\{}", components.join("\\")))
+} // normalize_windows_prefixed
+
+/// Checks if QCD_RS_NORMALIZE_SEPARATORS is set.
+fn normalize_separators() -> bool {
+    env::var(NORMSEP_KEY).is_ok()
+} // normalize_separators
+
+/// Replaces backslashes by forward slashes.
+fn unify_separators(path: &str) -> String {
+    path.replace('\\', "/")
+} // unify_separators
+
+/// Replaces forward slashes by the separator of the current platform.
+fn to_native_separators(path: &str) -> String {
+    path.replace('/', std::path::MAIN_SEPARATOR_STR)
+} // to_native_separators
+
+/// Representation of directory for printing. Uses the native separator
+/// if separators get normalized.
+fn native_dir(directory: &Utf8Path) -> String {
+    if normalize_separators() {
+        to_native_separators(directory.as_str())
+    } else {
+        directory.to_string()
+    }
+} // native_dir
+
+/// Converts path to style 'posix' (forward slashes), 'native' (as
+/// printed by default) or 'wsl' (Windows drive paths like 'C:\proj'
+/// become '/mnt/c/proj'). Returns an error for paths which cannot be
+/// converted to the requested style.
+fn styled_path(path: &str, style: &str) -> Result<String, String> {
+    match style {
+        "native" => Ok(native_dir(Utf8Path::new(path))),
+        "posix" => Ok(unify_separators(path)),
+        "wsl" => {
+            let unified = unify_separators(path);
+            let unified = unified.strip_prefix("//?/").unwrap_or(&unified);
+            let mut chars = unified.chars();
+            match (chars.next(), chars.next(), chars.as_str()) {
+                (Some(drive), Some(':'), rest)
+                    if drive.is_ascii_alphabetic()
+                        && (rest.is_empty() || rest.starts_with('/')) =>
+                {
+                    let rest = rest.trim_end_matches('/');
+                    Ok(format!("/mnt/{}{rest}", drive.to_ascii_lowercase()))
+                }
+                _ => Err(format!("{path} is no Windows drive path")),
+            }
+        }
+        _ => Err(format!("Unknown path style '{style}'")),
+    }
+} // styled_path
+
+/// Looks up the home directory of user in the password database.
+#[cfg(unix)]
+fn user_home(user: &str) -> Option<Utf8PathBuf> {
+    use std::ffi::{CStr, CString};
+
+    let name = CString::new(user).ok()?;
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        // SAFETY: all pointers are valid for the duration of the call, buf.len()
+        // is the size of buf and passwd is plain data that may be zeroed.
+        let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let rc = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut pwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if rc == libc::ERANGE && buf.len() < 1 << 20 {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if rc != 0 || result.is_null() || pwd.pw_dir.is_null() {
+            return None;
+        }
+        // SAFETY: pw_dir points to a NUL terminated string within buf
+        let dir = unsafe { CStr::from_ptr(pwd.pw_dir) };
+        return dir.to_str().ok().map(Utf8PathBuf::from);
+    }
+} // user_home
+
+#[cfg(not(unix))]
+fn user_home(_user: &str) -> Option<Utf8PathBuf> {
+    None
+} // user_home
+
+/// Expands a leading '~' to the home directory and '~user' to the home
+/// directory of user from the password database. Other paths are returned
+/// unchanged. Fails if user is unknown.
+fn expand_tilde(path: &Utf8Path) -> Result<Utf8PathBuf, String> {
+    let mut components = path.components();
+    let user = match components.next() {
+        Some(Utf8Component::Normal(first)) => match first.strip_prefix('~') {
+            Some(user) => user,
+            None => return Ok(path.to_path_buf()),
+        },
+        _ => return Ok(path.to_path_buf()),
+    };
+    let home = if user.is_empty() {
+        simple_home_dir::home_dir().and_then(|h| Utf8PathBuf::from_path_buf(h).ok())
+    } else {
+        match user_home(user) {
+            Some(h) => Some(h),
+            None => return Err(format!("Could not expand {path}: unknown user {user}")),
+        }
+    };
+    match home {
+        Some(h) => Ok(h.join(components.as_path())),
+        None => Ok(path.to_path_buf()),
+    }
+} // expand_tilde
+
+/// Tries to get a unique representation of a path.
+/// A leading '~' is expanded (see expand_tilde).
+///
+/// If QCD_RS_NORMALIZE_SEPARATORS is set all separators become '/'
+/// such that databases can be shared between Windows and Unix.
+fn clean_path(path: &Utf8PathBuf) -> Result<Utf8PathBuf, String> {
+    let cleaned = if cfg!(windows) {
+        normalize_windows_prefixed(path.as_str()).map(Utf8PathBuf::from)
+    } else {
+        None
+    };
+    let cleaned = match cleaned {
+        Some(pth) => pth,
+        None => {
+            let path = expand_tilde(path)?;
+            let new_path = path.as_std_path().absolutize();
+            match new_path {
+                Ok(pth) => match Utf8PathBuf::from_path_buf(pth.to_path_buf()) {
+                    Ok(pth) => pth,
+                    Err(_) => return Err("Only UTF-8 paths supported".to_string()),
+                },
+                Err(e) => return Err(format!("Could not get absolute path\n{e}")),
+            }
+        }
+    };
+    if normalize_separators() {
+        return Ok(Utf8PathBuf::from(unify_separators(cleaned.as_str())));
+    }
+    Ok(cleaned)
+} // clean_path
+
+/// Collapses '.' and '..' components of path without consulting the
+/// file system or the current work directory. Leading '..' components of
+/// relative paths are kept, '..' directly below the root is dropped.
+fn lexical_normalize(path: &Utf8Path) -> Utf8PathBuf {
+    let mut res = Vec::<Utf8Component>::new();
+    for c in path.components() {
+        match c {
+            Utf8Component::CurDir => {}
+            Utf8Component::ParentDir => match res.last() {
+                Some(Utf8Component::Normal(_)) => {
+                    res.pop();
+                }
+                Some(Utf8Component::RootDir) | Some(Utf8Component::Prefix(_)) => {}
+                _ => res.push(c),
+            },
+            _ => res.push(c),
+        }
+    }
+    if res.is_empty() {
+        return Utf8PathBuf::from(".");
+    }
+    res.iter().collect()
+} // lexical_normalize
+
+/// Like clean_path but with lexical normalization only, relative
+/// paths stay relative.
+fn clean_path_lexical(path: &Utf8PathBuf) -> Utf8PathBuf {
+    let cleaned = lexical_normalize(path);
+    if normalize_separators() {
+        return Utf8PathBuf::from(unify_separators(cleaned.as_str()));
+    }
+    cleaned
+} // clean_path_lexical
+
+/// Joins relative path sub onto base. Exits if the result is no directory.
+fn join_subdir(base: &Utf8Path, sub: &Utf8Path) -> Utf8PathBuf {
+    if sub.is_absolute() {
+        exit_with_error(ErrorKind::Usage, &format!("{sub} is no relative path"));
+    }
+    let joined = base.join(sub);
+    if !joined.is_dir() {
+        exit_with_error(
+            ErrorKind::NotFound,
+            &format!("{sub} is no directory in {base}: {joined}"),
+        );
+    }
+    check_and_unwrap(clean_path(&joined))
+} // join_subdir
+
+/// Exits if directory does not exist, unless allow_missing is set.
+fn check_exists(directory: &Utf8Path, allow_missing: bool) {
+    if !allow_missing && !directory.exists() {
+        exit_with_error(
+            ErrorKind::NotFound,
+            &format!("{directory} does not exist (see --allow-missing)"),
+        );
+    }
+} // check_exists
+
+/// Options controlling chdir, go and menu
+#[derive(Debug, Default)]
+pub struct ChdirOptions {
+    /// Accept directory of entry even if it does not exist
+    pub allow_missing: bool,
+    /// Print 'idx<TAB>alias<TAB>directory' instead of just the directory
+    pub porcelain: bool,
+    /// Days after which stack entries expire, None disables expiry
+    pub stack_expire: Option<i64>,
+    /// Maximum number of entries on the stack, None means no limit
+    pub stack_max: Option<u32>,
+    /// Settings of alias lookup
+    pub matching: db::AliasMatching,
+}
+
+/// Print directory associated with entry (joined with relative subdir),
+/// push push_dir onto stack. An entry like '^sub' refers to sub relative
+/// to the top of the stack ('^' alone to the top itself), idx and alias
+/// are empty then in porcelain output.
+/// Fails if the directory of entry does not exist, unless allow_missing is set.
+pub fn chdir(
+    db_name: &PathBuf,
+    table: &str,
+    entry: &str,
+    subdir: Option<&Utf8Path>,
+    push_dir: Option<Utf8PathBuf>,
+    sessionid: &str,
+    opts: &ChdirOptions,
+) -> ! {
+    let (base, row) = match entry.strip_prefix('^') {
+        Some(rel) => {
+            let conn = db::open_db(db_name);
+            let conn = check_and_unwrap(conn);
+            let top = db::Stack::new(&conn, sessionid, opts.stack_expire).peek();
+            let top = match top {
+                Ok(t) => t.directory,
+                Err(e) => exit_with_db_error(ErrorKind::Stack, &e),
+            };
+            let base = if rel.is_empty() {
+                top
+            } else {
+                join_subdir(&top, Utf8Path::new(rel))
+            };
+            (base, None)
+        }
+        None => {
+            let create_missing = matches!(env::var(ONMISSING_KEY), Ok(val) if val == "create");
+            let row = get_single_row(db_name, table, entry, create_missing, &opts.matching);
+            check_exists(&row.directory, opts.allow_missing);
+            record_visit(db_name, table, &row);
+            (row.directory.clone(), Some(row))
+        }
+    };
+
+    let directory = match subdir {
+        Some(sub) => join_subdir(&base, sub),
+        None => base,
+    };
+
+    if let Some(dir) = push_dir {
+        let _ = stack_push(
+            db_name,
+            sessionid,
+            opts.stack_expire,
+            opts.stack_max,
+            dir,
+            None,
+            false,
+        );
+    }
+
+    if opts.porcelain {
+        let (idx, alias) = match row {
+            Some(r) => (r.idx.to_string(), r.alias),
+            None => (String::new(), String::new()),
+        };
+        println!("{idx}\t{alias}\t{}", native_dir(&directory));
+    } else {
+        println!("{}", native_dir(&directory));
+    }
+    process::exit(EXIT_OK);
+} // chdir
+
+/// Records a visit of row (if it is contained in table).
+fn record_visit(db_name: &PathBuf, table: &str, row: &db::StdRow) {
+    if let Some(id) = row.id {
+        if let Ok(conn) = db::open_db(db_name) {
+            let _ = db::record_visit(&conn, table, id);
+        }
+    }
+} // record_visit
+
+/// Changes to entry if arg is an idx or alias, including the external
+/// resolver (see chdir). Otherwise arg has to be an existing directory,
+/// which is pushed onto the stack (if push_dir is given) and changed to.
+pub fn go(
+    db_name: &PathBuf,
+    table: &str,
+    arg: &str,
+    push_dir: Option<Utf8PathBuf>,
+    sessionid: &str,
+    opts: &ChdirOptions,
+) -> ! {
+    match lookup_row(db_name, table, arg, false, &opts.matching) {
+        Ok(row) => {
+            check_exists(&row.directory, opts.allow_missing);
+            record_visit(db_name, table, &row);
+            if let Some(dir) = push_dir {
+                let _ = stack_push(
+                    db_name,
+                    sessionid,
+                    opts.stack_expire,
+                    opts.stack_max,
+                    dir,
+                    None,
+                    false,
+                );
+            }
+            println!("{}", native_dir(&row.directory));
+            process::exit(EXIT_OK);
+        }
+        Err(e) if db::is_not_found(&e) => {}
+        Err(e) => exit_with_db_error(ErrorKind::General, &e),
+    }
+
+    let directory = Utf8PathBuf::from(arg);
+    if !directory.is_dir() {
+        exit_with_error(
+            ErrorKind::NotFound,
+            &format!("{arg} is neither an entry nor a directory"),
+        );
+    }
+    let directory = check_and_unwrap(clean_path(&directory));
+    if push_dir.is_some() {
+        let _ = stack_push(
+            db_name,
+            sessionid,
+            opts.stack_expire,
+            opts.stack_max,
+            directory.clone(),
+            None,
+            false,
+        );
+    }
+
+    println!("{}", native_dir(&directory));
+    process::exit(EXIT_OK);
+} // go
+
+/// Prints all entries to stderr and lets the user select one by idx or
+/// alias on stdin. Prints the selected directory, pushes push_dir onto stack.
+pub fn menu(
+    db_name: &PathBuf,
+    table: &str,
+    push_dir: Option<Utf8PathBuf>,
+    sessionid: &str,
+    opts: &ChdirOptions,
+) -> ! {
+    if !io::stdin().is_terminal() {
+        exit_with_error(ErrorKind::Usage, "Menu requires an interactive terminal");
+    }
+
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let entries = db::get_std_rows(&conn, table);
+    let entries = check_and_unwrap(entries);
+    if entries.is_empty() {
+        exit_with_error(ErrorKind::NotFound, "No entries in table");
+    }
+
+    let alias_len = entries.iter().fold(0, |m, e| cmp::max(m, e.alias.width()));
+    for entry in &entries {
+        eprintln!(
+            "{0:>4} {1} {2}",
+            entry.idx,
+            pad_alias(&entry.alias, alias_len, ' '),
+            entry.directory
+        );
+    }
+
+    let selected = read_selection(&mut io::stdin().lock(), |line| {
+        db::find_entry(&conn, table, &IdxAlias::from(line), &opts.matching)
+    });
+    let Some(row) = selected else {
+        eprintln!();
+        process::exit(EXIT_ERROR);
+    };
+    check_exists(&row.directory, opts.allow_missing);
+    record_visit(db_name, table, &row);
+
+    if let Some(dir) = push_dir {
+        let _ = stack_push(
+            db_name,
+            sessionid,
+            opts.stack_expire,
+            opts.stack_max,
+            dir,
+            None,
+            false,
+        );
+    }
+
+    println!("{}", native_dir(&row.directory));
+    process::exit(EXIT_OK);
+} // menu
+
+/// Prompts on stderr until a line read from input is accepted by find,
+/// empty lines and rejected selections prompt again. None on end of input.
+fn read_selection<T>(
+    input: &mut impl BufRead,
+    find: impl Fn(&str) -> Result<T, String>,
+) -> Option<T> {
+    loop {
+        eprint!("Select idx or alias: ");
+        let mut line = String::new();
+        match input.read_line(&mut line) {
+            Ok(0) | Err(_) => return None,
+            Ok(_) => {}
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match find(line) {
+            Ok(found) => return Some(found),
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+} // read_selection
+
+/// Returns the git directory of the repository in directory, None if
+/// directory is no git repository.
+fn git_dir(directory: &Utf8Path) -> Option<Utf8PathBuf> {
+    let git_dir = directory.join(".git");
+    if git_dir.is_file() {
+        // Worktrees and submodules contain a file 'gitdir: <path>'
+        let content = fs::read_to_string(&git_dir).ok()?;
+        let target = content.trim().strip_prefix("gitdir:")?.trim();
+        return Some(directory.join(target));
+    }
+    git_dir.is_dir().then_some(git_dir)
+} // git_dir
+
+/// Reads the current branch of the git repository in directory from
+/// '.git/HEAD'. Returns the abbreviated commit hash for a detached HEAD
+/// and None if directory is no git repository or cannot be read.
+fn git_branch(directory: &Utf8Path) -> Option<String> {
+    let git_dir = git_dir(directory)?;
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref:") {
+        Some(r) => {
+            let r = r.trim();
+            Some(r.strip_prefix("refs/heads/").unwrap_or(r).to_string())
+        }
+        None => Some(head.chars().take(7).collect()),
+    }
+} // git_branch
+
+/// Checks if directory exists. Returns None if this cannot be
+/// determined within STATTIMEOUT_MS (e.g. unreachable network drives).
+fn dir_exists_bounded(directory: &Utf8Path) -> Option<bool> {
+    let (tx, rx) = mpsc::channel();
+    let dir = directory.to_path_buf();
+    thread::spawn(move || {
+        let _ = tx.send(dir.is_dir());
+    });
+    rx.recv_timeout(Duration::from_millis(STATTIMEOUT_MS)).ok()
+} // dir_exists_bounded
+
+/// Checks if the git repository in directory has uncommitted changes without
+/// running git: a merge, rebase, cherry-pick or revert is in progress, the
+/// index has unmerged entries, or a tracked file is missing or differs in
+/// size or modification time from what the index recorded. Untracked files
+/// are not considered.
+fn git_dirty(directory: &Utf8Path) -> bool {
+    let Some(git_dir) = git_dir(directory) else {
+        return false;
+    };
+    let in_progress = [
+        "MERGE_HEAD",
+        "CHERRY_PICK_HEAD",
+        "REVERT_HEAD",
+        "rebase-merge",
+        "rebase-apply",
+    ]
+    .iter()
+    .any(|f| git_dir.join(f).exists());
+    if in_progress {
+        return true;
+    }
+    match fs::read(git_dir.join("index")) {
+        Ok(index) => index_differs(directory, &index).unwrap_or(false),
+        Err(_) => false,
+    }
+} // git_dirty
+
+/// Compares the entries of a git index file (versions 2 to 4) with the
+/// files in worktree. Returns None if the index cannot be parsed.
+fn index_differs(worktree: &Utf8Path, index: &[u8]) -> Option<bool> {
+    let be32 = |pos: usize| -> Option<u32> {
+        let b = index.get(pos..pos + 4)?;
+        Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let be16 = |pos: usize| -> Option<u16> {
+        let b = index.get(pos..pos + 2)?;
+        Some(u16::from_be_bytes([b[0], b[1]]))
+    };
+    if index.get(0..4)? != b"DIRC" {
+        return None;
+    }
+    let version = be32(4)?;
+    if !(2..=4).contains(&version) {
+        return None;
+    }
+    let count = be32(8)?;
+
+    let mut pos = 12;
+    let mut path: Vec<u8> = Vec::new();
+    for _ in 0..count {
+        let mtime = be32(pos + 8)?;
+        let mode = be32(pos + 24)?;
+        let size = be32(pos + 36)?;
+        let flags = be16(pos + 60)?;
+        let mut name = pos + 62;
+        let mut ext_flags = 0;
+        if version >= 3 && flags & 0x4000 != 0 {
+            ext_flags = be16(name)?;
+            name += 2;
+        }
+        if version == 4 {
+            // Path is prefix compressed: number of bytes to strip from the
+            // previous path, followed by the remaining suffix
+            let mut c = *index.get(name)?;
+            let mut strip = (c & 0x7f) as usize;
+            while c & 0x80 != 0 {
+                name += 1;
+                c = *index.get(name)?;
+                strip = ((strip + 1) << 7) + (c & 0x7f) as usize;
+            }
+            name += 1;
+            path.truncate(path.len().checked_sub(strip)?);
+        } else {
+            path.clear();
+        }
+        let len = index.get(name..)?.iter().position(|&b| b == 0)?;
+        path.extend_from_slice(&index[name..name + len]);
+        pos = if version == 4 {
+            name + len + 1
+        } else {
+            // Entries are padded with NULs to a multiple of eight bytes
+            pos + ((name - pos + len + 8) & !7)
+        };
+
+        if flags & 0x3000 != 0 {
+            return Some(true); // unmerged
+        }
+        let assume_valid = flags & 0x8000 != 0;
+        let skip_worktree = ext_flags & 0x4000 != 0;
+        let gitlink = mode >> 12 == 0o16;
+        if assume_valid || skip_worktree || gitlink {
+            continue;
+        }
+        let Ok(file) = std::str::from_utf8(&path) else {
+            continue;
+        };
+        let Ok(meta) = fs::symlink_metadata(worktree.join(file)) else {
+            return Some(true);
+        };
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as u32);
+        if meta.len() as u32 != size || modified.is_some_and(|m| m != mtime) {
+            return Some(true);
+        }
+    }
+    Some(false)
+} // index_differs
+
+/// Two character status of directory: '✓' exists, '✗' is missing,
+/// '?' is unknown. Second character is '*' if a git repository has
+/// uncommitted changes (see git_dirty).
+fn dir_status(directory: &Utf8Path) -> String {
+    match dir_exists_bounded(directory) {
+        Some(true) => {
+            let git = if git_dirty(directory) { '*' } else { ' ' };
+            format!("\u{2713}{git}")
+        }
+        Some(false) => "\u{2717} ".to_string(),
+        None => "? ".to_string(),
+    }
+} // dir_status
+
+/// Options controlling the output of list_dirs
+#[derive(Debug, Default)]
+pub struct ListOptions {
+    /// Append current git branch of repositories
+    pub git: bool,
+    /// Omit entries where alias or directory match this regular expression
+    pub hide_pattern: Option<String>,
+    /// Show if directories exist and if git repositories have uncommitted changes
+    pub status: bool,
+    /// Single character used for padding aliases (default: space)
+    pub fill: Option<String>,
+    /// Append additional aliases
+    pub long: bool,
+    /// Only list entries with absolute directory
+    pub absolute_only: bool,
+    /// Only list entries with relative directory
+    pub relative_only: bool,
+    /// Print one JSON object per entry and line
+    pub jsonl: bool,
+    /// Print all entries as a single JSON array
+    pub json: bool,
+    /// strftime-like format of times, relative times if None
+    pub time_format: Option<String>,
+    /// Timezone of formatted times: 'local' (default), 'UTC' or offset like '+02:00'
+    pub timezone: Option<String>,
+    /// List existing directories before missing (or unreachable) ones
+    pub existing_first: bool,
+    /// Only list entries below the directory of this entry, relative to it
+    pub under: Option<String>,
+    /// Settings of alias lookup (for the entry of under)
+    pub matching: db::AliasMatching,
+    /// Print entries as tree of their directories
+    pub tree: bool,
+    /// Order of entries: 'idx' (default) or 'frecency'
+    pub sort: Option<String>,
+}
+
+/// Score combining number of visits and time since last access, the
+/// higher the more relevant. Recent visits weigh more, like in z.
+fn frecency(visits: u32, last_access: i64, now: i64) -> f64 {
+    let age = now - last_access;
+    let weight = if age < 3600 {
+        4.0
+    } else if age < 24 * 3600 {
+        2.0
+    } else if age < 7 * 24 * 3600 {
+        0.5
+    } else {
+        0.25
+    };
+    visits as f64 * weight
+} // frecency
+
+/// JSON object with idx, alias and directory of entry.
+fn row_to_json(entry: &db::StdRow) -> String {
+    format!(
+        "{{\"idx\":{},\"alias\":\"{}\",\"directory\":\"{}\"}}",
+        entry.idx,
+        json_escape(&entry.alias),
+        json_escape(entry.directory.as_str())
+    )
+} // row_to_json
+
+/// Pads alias with fill to display width (terminal cells, not bytes).
+fn pad_alias(alias: &str, width: usize, fill: char) -> String {
+    let len = alias.width();
+    let mut padded = alias.to_string();
+    padded.extend(std::iter::repeat_n(fill, width.saturating_sub(len)));
+    padded
+} // pad_alias
+
+/// Node of the directory tree printed by 'qcd -l --tree'
+#[derive(Default)]
+struct TreeNode {
+    /// Entries (idx and alias) with exactly this directory
+    entries: Vec<(u32, String)>,
+    children: BTreeMap<String, TreeNode>,
+}
+
+/// Appends lines of node and its children indented by depth to lines.
+/// Chains of nodes without entries and a single child are merged.
+fn tree_lines(label: &Utf8Path, node: &TreeNode, depth: usize, lines: &mut Vec<String>) {
+    let mut label = label.to_path_buf();
+    let mut node = node;
+    while node.entries.is_empty() && node.children.len() == 1 {
+        let (name, child) = node.children.iter().next().unwrap();
+        label.push(name);
+        node = child;
+    }
+    let mut line = format!("{}{}", "  ".repeat(depth), label);
+    for (idx, alias) in &node.entries {
+        if alias.is_empty() {
+            line.push_str(&format!(" [{idx}]"));
+        } else {
+            line.push_str(&format!(" [{idx} {alias}]"));
+        }
+    }
+    lines.push(line);
+    for (name, child) in &node.children {
+        tree_lines(Utf8Path::new(name), child, depth + 1, lines);
+    }
+} // tree_lines
+
+/// Lines of an indented tree of entries, merging common leading path
+/// components. Entries without common components result in a flat list.
+fn dir_tree(entries: &[db::StdRow]) -> Vec<String> {
+    let mut root = TreeNode::default();
+    for e in entries {
+        // Root and prefix stay attached to the first component
+        let mut names = Vec::<String>::new();
+        let mut lead = Utf8PathBuf::new();
+        for c in e.directory.components() {
+            match c {
+                Utf8Component::Prefix(_) | Utf8Component::RootDir => lead.push(c),
+                _ if names.is_empty() => {
+                    lead.push(c);
+                    names.push(lead.to_string());
+                }
+                _ => names.push(c.to_string()),
+            }
+        }
+        if names.is_empty() {
+            names.push(lead.to_string());
+        }
+        let node = names
+            .into_iter()
+            .fold(&mut root, |n, name| n.children.entry(name).or_default());
+        node.entries.push((e.idx, e.alias.clone()));
+    }
+
+    let mut lines = Vec::new();
+    for (name, child) in &root.children {
+        tree_lines(Utf8Path::new(name), child, 0, &mut lines);
+    }
+    lines
+} // dir_tree
+
+/// Prints all entries of the specified table sorted by idx.
+pub fn list_dirs(db_name: &PathBuf, table: &str, opts: &ListOptions) -> ! {
+    let hide = match &opts.hide_pattern {
+        Some(p) => match Regex::new(p) {
+            Ok(re) => Some(re),
+            Err(e) => exit_with_error(ErrorKind::Usage, &format!("Invalid hide pattern\n{e}")),
+        },
+        None => None,
+    };
+    let fill = match &opts.fill {
+        Some(f) => {
+            let mut chars = f.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => exit_with_error(ErrorKind::Usage, "Fill must be a single character"),
+            }
+        }
+        None => ' ',
+    };
+
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let entries = db::get_std_rows(&conn, table);
+    let entries = check_and_unwrap(entries);
+    let (extra_aliases, expiries) = if opts.long {
+        (
+            check_and_unwrap(db::get_aliases(&conn)),
+            check_and_unwrap(db::get_expiries(&conn, table)),
+        )
+    } else {
+        (Vec::new(), Vec::new())
+    };
+    let time_format = opts.time_format.as_deref();
+    let timezone = opts.timezone.as_deref();
+    let now = Utc::now().timestamp();
+    if opts.long {
+        if let Err(e) = format_time(now, now, time_format, timezone) {
+            exit_with_error(ErrorKind::Usage, &e);
+        }
+    }
+    // Entries of databases from search path are tagged with their file name
+    let mut entries: Vec<(db::StdRow, String)> =
+        entries.into_iter().map(|e| (e, String::new())).collect();
+    for path in search_path_dbs(db_name) {
+        let conn = check_and_unwrap(db::open_db_readonly(&path));
+        let rows = check_and_unwrap(db::get_std_rows(&conn, table));
+        let tag = format!(" (from {})", path.file_name().unwrap().to_string_lossy());
+        entries.extend(rows.into_iter().map(|mut e| {
+            e.id = None;
+            (e, tag.clone())
+        }));
+    }
+    if let Some(re) = hide {
+        entries.retain(|(e, _)| !re.is_match(&e.alias) && !re.is_match(e.directory.as_str()));
+    }
+    if opts.absolute_only {
+        entries.retain(|(e, _)| e.directory.is_absolute());
+    }
+    if opts.relative_only {
+        entries.retain(|(e, _)| e.directory.is_relative());
+    }
+    let root = opts
+        .under
+        .as_ref()
+        .map(|entry| get_single_row(db_name, table, entry, false, &opts.matching).directory);
+    if let Some(root) = &root {
+        entries.retain(|(e, _)| e.directory.starts_with(root));
+    }
+    if opts.tree {
+        let rows: Vec<db::StdRow> = entries.into_iter().map(|(e, _)| e).collect();
+        for line in dir_tree(&rows) {
+            println!("{line}");
+        }
+        process::exit(EXIT_OK);
+    }
+    if opts.sort.as_deref() == Some("frecency") {
+        let visits = check_and_unwrap(db::get_visits(&conn, table));
+        let score = |e: &db::StdRow| match visits.iter().find(|(id, _, _)| Some(*id) == e.id) {
+            Some((_, n, ts)) => frecency(*n, *ts, now),
+            None => 0.0,
+        };
+        entries.sort_by(|(a, _), (b, _)| score(b).total_cmp(&score(a)));
+    }
+    if opts.existing_first {
+        let (mut existing, missing): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .partition(|(e, _)| dir_exists_bounded(&e.directory) == Some(true));
+        existing.extend(missing);
+        entries = existing;
+    }
+
+    if opts.json {
+        let objects: Vec<String> = entries.iter().map(|(e, _)| row_to_json(e)).collect();
+        println!("[{}]", objects.join(","));
+        process::exit(EXIT_OK);
+    }
+
+    let alias_len = entries
+        .iter()
+        .fold(0, |m, (e, _)| cmp::max(m, e.alias.width()));
+    for (entry, source) in entries {
+        if opts.jsonl {
+            println!("{}", row_to_json(&entry));
+            continue;
+        }
+        let status = if opts.status {
+            format!("{} ", dir_status(&entry.directory))
+        } else {
+            String::new()
+        };
+        let branch = if opts.git {
+            git_branch(&entry.directory)
+                .map(|b| format!(" [{b}]"))
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+        let extra: Vec<&str> = extra_aliases
+            .iter()
+            .filter(|(id, _)| Some(*id) == entry.id)
+            .map(|(_, a)| a.as_str())
+            .collect();
+        let extra = if extra.is_empty() {
+            String::new()
+        } else {
+            format!(" (also: {})", extra.join(", "))
+        };
+        let expiry = match expiries.iter().find(|(id, _)| Some(*id) == entry.id) {
+            Some((_, ts)) => {
+                let time = format_time(*ts, now, time_format, timezone);
+                format!(" (expires {})", check_and_unwrap(time))
+            }
+            None => String::new(),
+        };
+        let directory = match &root {
+            Some(r) => match entry.directory.strip_prefix(r) {
+                Ok(d) if d.as_str().is_empty() => Utf8Path::new("."),
+                Ok(d) => d,
+                Err(_) => &entry.directory,
+            },
+            None => &entry.directory,
+        };
+        println!(
+            "{0:>4} {status}{1} {2}{branch}{extra}{expiry}{source}",
+            entry.idx,
+            pad_alias(&entry.alias, alias_len, fill),
+            directory
+        );
+    }
+    process::exit(EXIT_OK);
+} // list_dirs
+
+/// Number with unit, plural if necessary.
+fn count_unit(n: i64, unit: &str) -> String {
+    if n == 1 {
+        format!("1 {unit}")
+    } else {
+        format!("{n} {unit}s")
+    }
+} // count_unit
+
+/// Renders ts (seconds since epoch) relative to now, e.g. 'in 3 days' or
+/// '2 hours ago'. If format is given, ts is rendered as absolute time in
+/// timezone instead.
+fn format_time(
+    ts: i64,
+    now: i64,
+    format: Option<&str>,
+    timezone: Option<&str>,
+) -> Result<String, String> {
+    let Some(format) = format else {
+        let diff = (ts - now).abs();
+        let span = match diff {
+            d if d < 60 => return Ok("now".to_string()),
+            d if d < 60 * 60 => count_unit(d / 60, "minute"),
+            d if d < 24 * 60 * 60 => count_unit(d / (60 * 60), "hour"),
+            d => count_unit(d / (24 * 60 * 60), "day"),
+        };
+        return Ok(if ts > now {
+            format!("in {span}")
+        } else {
+            format!("{span} ago")
+        });
+    };
+
+    let items: Vec<Item> = StrftimeItems::new(format).collect();
+    if items.iter().any(|i| matches!(i, Item::Error)) {
+        return Err(format!("Invalid time format '{format}'"));
+    }
+    let utc = match Utc.timestamp_opt(ts, 0).single() {
+        Some(t) => t,
+        None => return Err(format!("Invalid timestamp {ts}")),
+    };
+    let formatted = match timezone {
+        None | Some("local") => utc
+            .with_timezone(&Local)
+            .format_with_items(items.iter())
+            .to_string(),
+        Some("UTC") => utc.format_with_items(items.iter()).to_string(),
+        Some(tz) => match tz.parse::<FixedOffset>() {
+            Ok(offset) => utc
+                .with_timezone(&offset)
+                .format_with_items(items.iter())
+                .to_string(),
+            Err(_) => return Err(format!("Invalid timezone '{tz}'")),
+        },
+    };
+    Ok(formatted)
+} // format_time
+
+/// Returns all non-empty aliases used more than once together with the
+/// idx values of the entries using them. Sorted by alias.
+fn duplicate_aliases(aliases: &[(String, u32)]) -> Vec<(String, Vec<u32>)> {
+    let mut sorted: Vec<&(String, u32)> = aliases.iter().filter(|a| !a.0.is_empty()).collect();
+    sorted.sort();
+    let mut dups = Vec::<(String, Vec<u32>)>::new();
+    for pair in sorted.windows(2) {
+        if pair[0].0 != pair[1].0 {
+            continue;
+        }
+        match dups.last_mut() {
+            Some(d) if d.0 == pair[0].0 => d.1.push(pair[1].1),
+            _ => dups.push((pair[0].0.clone(), vec![pair[0].1, pair[1].1])),
+        }
+    }
+    dups
+} // duplicate_aliases
+
+/// Reports aliases (including additional ones) used by several entries.
+/// Exits with EXIT_ERROR if there are any.
+pub fn check_aliases(db_name: &PathBuf, table: &str) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let entries = db::get_std_rows(&conn, table);
+    let entries = check_and_unwrap(entries);
+    let extra_aliases = db::get_aliases(&conn);
+    let extra_aliases = check_and_unwrap(extra_aliases);
+
+    let mut aliases: Vec<(String, u32)> =
+        entries.iter().map(|e| (e.alias.clone(), e.idx)).collect();
+    for (id, alias) in extra_aliases {
+        if let Some(e) = entries.iter().find(|e| e.id == Some(id)) {
+            aliases.push((alias, e.idx));
+        }
+    }
+
+    let dups = duplicate_aliases(&aliases);
+    for (alias, idxs) in &dups {
+        let idxs: Vec<String> = idxs.iter().map(|i| i.to_string()).collect();
+        println!("Alias '{alias}' used by idx {}", idxs.join(", "));
+    }
+    if dups.is_empty() {
+        println!("No duplicate aliases");
+        process::exit(EXIT_OK);
+    }
+    process::exit(EXIT_ERROR);
+} // check_aliases
+
+/// Quotes s for usage as a single word in POSIX shells.
+fn shell_quote(s: &str) -> String {
+    if !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/._-+,:@%".contains(c))
+    {
+        return s.to_string();
+    }
+    format!("'{}'", s.replace('\'', "'\\''"))
+} // shell_quote
+
+/// Builds the name of an environment variable from prefix and alias.
+/// The alias is uppercased, '-', '.' and ' ' become '_'. Returns None if
+/// the result is no valid identifier.
+fn env_var_name(prefix: &str, alias: &str) -> Option<String> {
+    let mut name = prefix.to_string();
+    for c in alias.chars() {
+        match c {
+            '-' | '.' | ' ' => name.push('_'),
+            c if c.is_ascii_alphanumeric() || c == '_' => name.push(c.to_ascii_uppercase()),
+            _ => return None,
+        }
+    }
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return None,
+    }
+    if chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        Some(name)
+    } else {
+        None
+    }
+} // env_var_name
+
+/// Prints an export statement for each entry with an alias.
+pub fn export_env(db_name: &PathBuf, table: &str, prefix: &str) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let entries = db::get_std_rows(&conn, table);
+    let entries = check_and_unwrap(entries);
+
+    for entry in entries.iter().filter(|e| !e.alias.is_empty()) {
+        match env_var_name(prefix, &entry.alias) {
+            Some(name) => println!("export {}={}", name, shell_quote(entry.directory.as_str())),
+            None => eprintln!("Skipping alias '{}': no valid variable name", entry.alias),
+        }
+    }
+    process::exit(EXIT_OK);
+} // export_env
+
+/// Add one row to tables like 'main'. With lexical set directory is only
+/// normalized lexically (see clean_path_lexical). The first of aliases
+/// becomes the alias of the row, the others additional aliases.
+pub fn add_row(
+    db_name: &PathBuf,
+    table: &str,
+    idx: Option<u32>,
+    directory: Utf8PathBuf,
+    aliases: Vec<String>,
+    lexical: bool,
+    expire: Option<Duration>,
+) -> ! {
+    let expires_at = expire.map(
+        |ttl| match expiry_timestamp(Local::now().timestamp(), ttl) {
+            Some(ts) => ts,
+            None => exit_with_error(ErrorKind::Usage, "Time span of --expire is too long"),
+        },
+    );
+
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let mut aliases = aliases.into_iter();
+    let alias = aliases.next().unwrap_or_default();
+    let extra: Vec<String> = aliases.collect();
+    let clean_dir = if lexical {
+        clean_path_lexical(&directory)
+    } else {
+        check_and_unwrap(clean_path(&directory))
+    };
+    // Without idx the next free one is determined when inserting
+    let entry = db::StdRow {
+        id: None,
+        idx: idx.unwrap_or(0),
+        directory: clean_dir,
+        alias,
+    };
+    let new_idx =
+        db::add_std_dir_with_aliases(&conn, table, &entry, &extra, expires_at, idx.is_none());
+    let new_idx = check_and_unwrap(new_idx);
+    println!("Path added with index {new_idx}");
+    if !extra.is_empty() {
+        println!("Aliases: {}, {}", entry.alias, extra.join(", "));
+    }
+    process::exit(EXIT_OK);
+} // add_row
+
+/// Checks whether directory (cleaned like in add_row) is already contained
+/// in table. If so, exits silently with EXIT_OK if only_if_changed is set
+/// (reporting the index if verbose is set) or fails naming the entry otherwise.
+pub fn check_registered(
+    db_name: &PathBuf,
+    table: &str,
+    directory: &Utf8PathBuf,
+    lexical: bool,
+    only_if_changed: bool,
+    verbose: bool,
+) {
+    let clean_dir = if lexical {
+        clean_path_lexical(directory)
+    } else {
+        check_and_unwrap(clean_path(directory))
+    };
+
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+    if let Ok(row) = db::search_dir(&conn, table, &clean_dir) {
+        let mut msg = format!("Directory already registered with index {}", row.idx);
+        if !row.alias.is_empty() {
+            msg += &format!(" (alias {})", row.alias);
+        }
+        if !only_if_changed {
+            exit_with_error(
+                ErrorKind::General,
+                &format!("{msg} (see --allow-duplicate)"),
+            );
+        }
+        if verbose {
+            println!("{msg}");
+        }
+        process::exit(EXIT_OK);
+    }
+} // check_registered
+
+/// Returns the existing directory levels above directory.
+pub fn parent_dir(directory: &Utf8Path, levels: usize) -> Result<Utf8PathBuf, String> {
+    let parent = directory.ancestors().nth(levels);
+    match parent {
+        Some(p) if p.is_dir() => Ok(p.to_path_buf()),
+        Some(p) => Err(format!("{p} is not an existing directory")),
+        None => Err(format!("{directory} has no parent {levels} levels up")),
+    }
+} // parent_dir
+
+/// Exits with a usage error if alias could not be matched as alias
+/// (see db::check_alias), unless force is set.
+pub fn validate_alias(alias: &str, force: bool) {
+    if force {
+        return;
+    }
+    if let Err(e) = db::check_alias(alias) {
+        exit_with_error(
+            ErrorKind::Usage,
+            &format!("{e} (use --force to add it anyway)"),
+        );
+    }
+} // validate_alias
+
+/// Parses a time span like '30m', '12h', '14d' or '2w'. Spans which do
+/// not fit into a timestamp are rejected.
+pub fn parse_ttl(ttl: &str) -> Result<Duration, String> {
+    let err = || format!("Invalid time span '{ttl}' (e.g. 30m, 12h, 14d, 2w)");
+    let unit = ttl.chars().last().ok_or_else(err)?;
+    let secs = match unit {
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return Err(err()),
+    };
+    let n = ttl[..ttl.len() - 1].parse::<u64>().map_err(|_| err())?;
+    if n == 0 {
+        return Err(err());
+    }
+    match n.checked_mul(secs) {
+        Some(s) if i64::try_from(s).is_ok() => Ok(Duration::from_secs(s)),
+        _ => Err(err()),
+    }
+} // parse_ttl
+
+/// Returns now plus ttl (seconds since epoch) if that is a valid point
+/// in time.
+fn expiry_timestamp(now: i64, ttl: Duration) -> Option<i64> {
+    let secs = i64::try_from(ttl.as_secs()).ok()?;
+    let ts = now.checked_add(secs)?;
+    Utc.timestamp_opt(ts, 0).single().map(|_| ts)
+} // expiry_timestamp
+
+/// Add directory with alias and next free idx. Fails if directory is
+/// already contained in table unless force is set.
+pub fn bookmark(
+    db_name: &PathBuf,
+    table: &str,
+    directory: Utf8PathBuf,
+    alias: String,
+    force: bool,
+) -> ! {
+    if !force {
+        check_registered(db_name, table, &directory, false, false, false);
+    }
+
+    add_row(db_name, table, None, directory, vec![alias], false, None);
+} // bookmark
+
+/// Returns the entries whose directory equals old or lies below old
+/// together with their directory where old is replaced by new.
+/// Only complete path components are matched.
+fn rewritten_dirs(
+    entries: Vec<db::StdRow>,
+    old: &Utf8Path,
+    new: &Utf8Path,
+) -> Vec<(db::StdRow, Utf8PathBuf)> {
+    entries
+        .into_iter()
+        .filter_map(|e| {
+            let rest = e.directory.strip_prefix(old).ok()?;
+            let new_dir = if rest.as_str().is_empty() {
+                new.to_path_buf()
+            } else {
+                new.join(rest)
+            };
+            Some((e, new_dir))
+        })
+        .collect()
+} // rewritten_dirs
+
+/// Replaces prefix old of all directories by new. Just prints
+/// the changes if dry_run is set.
+pub fn rewrite_prefix(
+    db_name: &PathBuf,
+    table: &str,
+    old: Utf8PathBuf,
+    new: Utf8PathBuf,
+    dry_run: bool,
+) -> ! {
+    let old = check_and_unwrap(clean_path(&old));
+    let new = check_and_unwrap(clean_path(&new));
+
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let entries = db::get_std_rows(&conn, table);
+    let entries = check_and_unwrap(entries);
+
+    let changes = rewritten_dirs(entries, &old, &new);
+    for (e, new_dir) in &changes {
+        println!("{0:>4} {1} -> {2}", e.idx, e.directory, new_dir);
+    }
+    if dry_run {
+        println!("{} entries would change", changes.len());
+        process::exit(EXIT_OK);
+    }
+
+    let ids: Vec<(u64, Utf8PathBuf)> = changes
+        .into_iter()
+        .map(|(e, new_dir)| (e.id.unwrap(), new_dir))
+        .collect();
+    let res = db::set_directories(&conn, table, &ids);
+    check_and_unwrap(res);
+    println!("{} entries changed", ids.len());
+    process::exit(EXIT_OK);
+} // rewrite_prefix
+
+/// Returns the entries sharing their idx with another entry together with
+/// a fresh idx (above the highest idx) for each of them. Of all entries
+/// with the same idx, the one with the highest id keeps it.
+fn reassigned_idxs(mut entries: Vec<db::StdRow>) -> Vec<(db::StdRow, u32)> {
+    entries.sort_by_key(|e| (e.idx, cmp::Reverse(e.id)));
+    let mut next_idx = entries.iter().map(|e| e.idx).max().unwrap_or(0);
+    let mut last_idx = None;
+    let mut changes = Vec::new();
+    for e in entries {
+        if last_idx == Some(e.idx) {
+            next_idx += 1;
+            changes.push((e, next_idx));
+        } else {
+            last_idx = Some(e.idx);
+        }
+    }
+    changes
+} // reassigned_idxs
+
+/// Gives entries with duplicate idx values fresh idx values. Just prints
+/// the changes if dry_run is set.
+pub fn fix_idx(db_name: &PathBuf, table: &str, dry_run: bool) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let entries = db::get_std_rows(&conn, table);
+    let entries = check_and_unwrap(entries);
+
+    let changes = reassigned_idxs(entries);
+    for (e, new_idx) in &changes {
+        println!("{0:>4} -> {1:>4} {2}", e.idx, new_idx, e.directory);
+    }
+    if dry_run {
+        println!("{} entries would change", changes.len());
+        process::exit(EXIT_OK);
+    }
+
+    let ids: Vec<(u64, u32)> = changes
+        .into_iter()
+        .map(|(e, new_idx)| (e.id.unwrap(), new_idx))
+        .collect();
+    let res = db::set_idxs(&conn, table, &ids);
+    check_and_unwrap(res);
+    println!("{} entries changed", ids.len());
+    process::exit(EXIT_OK);
+} // fix_idx
+
+/// Set new idx or alias for row corresponding to idx. Just prints
+/// the change if dry_run is set.
+pub fn update_row(db_name: &PathBuf, table: &str, idx: u32, entry: &IdxAlias, dry_run: bool) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    if dry_run {
+        let row = db::find_entry(&conn, table, &Idx(idx), &db::AliasMatching::default());
+        let row = check_and_unwrap(row);
+        let change = match entry {
+            IdxAlias::Idx(i) => format!("idx {i}"),
+            IdxAlias::Alias(a) => format!("alias {a}"),
+        };
+        println!(
+            "{0:>4} {1} {2} -> {change}",
+            row.idx, row.alias, row.directory
+        );
+        process::exit(EXIT_OK);
+    }
+    let res = db::update_entry(&conn, table, idx, entry);
+    check_and_unwrap(res);
+
+    process::exit(EXIT_OK);
+} // update_row
+
+/// Sets path of row corresponding to entry (idx or alias) to cleaned
+/// directory. Fails if directory does not exist, unless allow_missing is set.
+pub fn update_path(
+    db_name: &PathBuf,
+    table: &str,
+    entry: &str,
+    directory: Utf8PathBuf,
+    allow_missing: bool,
+    matching: &db::AliasMatching,
+) -> ! {
+    let clean_dir = clean_path(&directory);
+    let clean_dir = check_and_unwrap(clean_dir);
+    check_exists(&clean_dir, allow_missing);
+
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let res = db::update_directory(&conn, table, &IdxAlias::from(entry), &clean_dir, matching);
+    check_and_unwrap(res);
+
+    process::exit(EXIT_OK);
+} // update_path
+
+/// Moves row corresponding to idx to idx 1, shifting rows with lower idx
+pub fn promote(db_name: &PathBuf, table: &str, idx: u32) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let res = db::promote_entry(&conn, table, idx);
+    check_and_unwrap(res);
+
+    process::exit(EXIT_OK);
+} // promote
+
+/// Moves row corresponding to idx to the highest idx, shifting rows with higher idx
+pub fn demote(db_name: &PathBuf, table: &str, idx: u32) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let res = db::demote_entry(&conn, table, idx);
+    check_and_unwrap(res);
+
+    process::exit(EXIT_OK);
+} // demote
+
+/// Moves row corresponding to entry (idx or alias) to new_idx. If new_idx
+/// is taken, the rows in between are shifted by one.
+pub fn move_row(
+    db_name: &PathBuf,
+    table: &str,
+    entry: &str,
+    new_idx: u32,
+    matching: &db::AliasMatching,
+) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let res = db::move_entry(&conn, table, &IdxAlias::from(entry), new_idx, matching);
+    check_and_unwrap(res);
+
+    process::exit(EXIT_OK);
+} // move_row
+
+/// Renumbers all rows to idx values 1, 2, ... keeping their order
+pub fn compact(db_name: &PathBuf, table: &str) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let res = db::compact_entries(&conn, table);
+    let changed = check_and_unwrap(res);
+    println!("{changed} entries changed");
+
+    process::exit(EXIT_OK);
+} // compact
+
+/// Sets alias of row corresponding to entry (idx or alias)
+pub fn rename(
+    db_name: &PathBuf,
+    table: &str,
+    entry: &str,
+    alias: &str,
+    matching: &db::AliasMatching,
+) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let row = db::find_entry(&conn, table, &IdxAlias::from(entry), matching);
+    let row = check_and_unwrap(row);
+    let res = db::update_entry(&conn, table, row.idx, &IdxAlias::Alias(alias.to_string()));
+    check_and_unwrap(res);
+
+    process::exit(EXIT_OK);
+} // rename
+
+/// Exchanges aliases of the rows corresponding to a and b (idx or alias)
+pub fn swap_alias(
+    db_name: &PathBuf,
+    table: &str,
+    a: &str,
+    b: &str,
+    matching: &db::AliasMatching,
+) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let res = db::swap_aliases(
+        &conn,
+        table,
+        &IdxAlias::from(a),
+        &IdxAlias::from(b),
+        matching,
+    );
+    check_and_unwrap(res);
+
+    process::exit(EXIT_OK);
+} // swap_alias
+
+/// Adds an additional alias to row corresponding to idx
+pub fn add_alias(db_name: &PathBuf, table: &str, idx: u32, alias: &str) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let res = db::add_alias(&conn, table, idx, alias);
+    check_and_unwrap(res);
+
+    process::exit(EXIT_OK);
+} // add_alias
+
+/// Removes an additional alias from row corresponding to idx
+pub fn del_alias(db_name: &PathBuf, table: &str, idx: u32, alias: &str) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let res = db::rm_alias(&conn, table, idx, alias);
+    check_and_unwrap(res);
+
+    process::exit(EXIT_OK);
+} // del_alias
+
+/// Asks the command given by QCD_RS_RESOLVER for the directory of entry.
+/// The command gets entry as single argument, its output is used
+/// if it exits with 0.
+fn external_resolve(entry: &str) -> Option<Utf8PathBuf> {
+    let resolver = env::var(RESOLVER_KEY).ok()?;
+    let output = process::Command::new(resolver)
+        .arg(entry)
+        .stderr(process::Stdio::inherit())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let dir = String::from_utf8(output.stdout).ok()?;
+    let dir = dir.trim_end_matches(['\n', '\r']);
+    if dir.is_empty() || dir.contains('\n') {
+        return None;
+    }
+    clean_path(&Utf8PathBuf::from(dir)).ok()
+} // external_resolve
+
+/// Existing databases listed in QCD_RS_DB_SEARCH_PATH except db_name.
+fn search_path_dbs(db_name: &Path) -> Vec<PathBuf> {
+    match env::var_os(SEARCHPATH_KEY) {
+        Some(paths) => env::split_paths(&paths)
+            .filter(|p| p.is_file() && p != db_name)
+            .collect(),
+        None => Vec::new(),
+    }
+} // search_path_dbs
+
+/// Searches for the row corresponding to entry in conn, then in the
+/// databases from QCD_RS_DB_SEARCH_PATH in order (opened read-only).
+/// Rows from the latter have no id. Returns the error of conn if entry is not found anywhere.
+/// Rows without valid directory (NULL in database) result in an error.
+fn find_entry_layered(
+    conn: &Connection,
+    db_name: &Path,
+    table: &str,
+    entry: &IdxAlias,
+    matching: &db::AliasMatching,
+) -> Result<db::StdRow, String> {
+    let err = match db::find_entry(conn, table, entry, matching) {
+        Ok(row) if row.directory.as_str().is_empty() => {
+            return Err(format!("Entry {} has no valid directory", row.idx))
+        }
+        Ok(row) => return Ok(row),
+        Err(e) if db::is_not_found(&e) => e,
+        Err(e) => return Err(e),
+    };
+    for path in search_path_dbs(db_name) {
+        let other = db::open_db_readonly(&path)?;
+        match db::find_entry(&other, table, entry, matching) {
+            Ok(mut row) => {
+                row.id = None;
+                return Ok(row);
+            }
+            Err(e) if db::is_not_found(&e) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Err(err)
+} // find_entry_layered
+
+/// Searches for the row corresponding to entry like lookup_row, exits
+/// if it is not found.
+fn get_single_row(
+    db_name: &PathBuf,
+    table: &str,
+    entry: &str,
+    create_missing: bool,
+    matching: &db::AliasMatching,
+) -> db::StdRow {
+    match lookup_row(db_name, table, entry, create_missing, matching) {
+        Ok(r) => r,
+        Err(e) => exit_with_db_error(ErrorKind::General, &e),
+    }
+} // get_single_row
+
+/// Searches for the row corresponding to entry. Falls back to the
+/// external resolver if entry is not found. Rows from the resolver have
+/// no id unless QCD_RS_RESOLVER_ADD is set, which adds them to table.
+/// Finally, if create_missing is set and entry is an existing directory,
+/// it is added to table. Only returns an error if entry is not found,
+/// exits on other errors.
+fn lookup_row(
+    db_name: &PathBuf,
+    table: &str,
+    entry: &str,
+    create_missing: bool,
+    matching: &db::AliasMatching,
+) -> Result<db::StdRow, String> {
+    let idx_alias = db::IdxAlias::from(entry);
+
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+    let row = find_entry_layered(&conn, db_name, table, &idx_alias, matching);
+    let err = match row {
+        Ok(r) => return Ok(r),
+        Err(e) => e,
+    };
+    if !db::is_not_found(&err) {
+        exit_with_db_error(ErrorKind::General, &err);
+    }
+
+    let (directory, add) = if let Some(d) = external_resolve(entry) {
+        (d, env::var(RESOLVERADD_KEY).is_ok())
+    } else if create_missing && Utf8Path::new(entry).is_dir() {
+        (
+            check_and_unwrap(clean_path(&Utf8PathBuf::from(entry))),
+            true,
+        )
+    } else {
+        return Err(err);
+    };
+    if let Ok(r) = db::search_dir(&conn, table, &directory) {
+        return Ok(r);
+    }
+    let mut row = db::StdRow {
+        id: None,
+        idx: 0,
+        directory,
+        alias: "".to_string(),
+    };
+    if add {
+        let res = db::add_std_dir_next_idx(&conn, table, &row);
+        let idx = check_and_unwrap(res);
+        let res = db::find_entry(&conn, table, &Idx(idx), &db::AliasMatching::default());
+        row = check_and_unwrap(res);
+    }
+    Ok(row)
+} // lookup_row
+
+/// Searches for directory name, prints idx value if found, prints -1 otherwise.
+/// If strict is set nothing is printed but EXIT_NOTFOUND returned instead of -1.
+/// If ensure is set a missing directory is added (next free idx) and its idx printed.
+/// If all is set the idx values of all entries with directory are printed.
+pub fn find_directory(
+    db_name: &PathBuf,
+    table: &str,
+    directory: Utf8PathBuf,
+    lexical: bool,
+    strict: bool,
+    ensure: bool,
+    all: bool,
+) -> ! {
+    let clean_dir = if lexical {
+        clean_path_lexical(&directory)
+    } else {
+        check_and_unwrap(clean_path(&directory))
+    };
+
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+    let idxs: Vec<u32> = if all {
+        let entries = db::get_std_rows(&conn, table);
+        check_and_unwrap(entries)
+            .into_iter()
+            .filter(|e| e.directory == clean_dir)
+            .map(|e| e.idx)
+            .collect()
+    } else {
+        db::search_dir(&conn, table, &clean_dir)
+            .map(|r| r.idx)
+            .into_iter()
+            .collect()
+    };
+    for idx in &idxs {
+        println!("{idx}");
+    }
+    if idxs.is_empty() {
+        if ensure {
+            let entry = db::StdRow {
+                id: None,
+                idx: 0,
+                directory: clean_dir,
+                alias: "".to_string(),
+            };
+            let new_idx = db::add_std_dir_next_idx(&conn, table, &entry);
+            println!("{}", check_and_unwrap(new_idx));
+        } else if strict {
+            process::exit(EXIT_NOTFOUND);
+        } else {
+            println!("-1");
+        }
+    }
+    process::exit(EXIT_OK);
+} // find_directory
+
+/// Reads directory names from stdin (one per line) and prints the idx
+/// value of each or -1 if it is not found. Blank lines are echoed.
+pub fn find_directories(db_name: &PathBuf, table: &str, lexical: bool) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(e) => exit_with_error(
+                ErrorKind::General,
+                &format!("Could not read from stdin\n{e}"),
+            ),
+        };
+        if line.trim().is_empty() {
+            println!();
+            continue;
+        }
+        let directory = Utf8PathBuf::from(line);
+        let clean_dir = if lexical {
+            clean_path_lexical(&directory)
+        } else {
+            check_and_unwrap(clean_path(&directory))
+        };
+        match db::search_dir(&conn, table, &clean_dir) {
+            Ok(r) => println!("{}", r.idx),
+            Err(_) => println!("-1"),
+        }
+    }
+    process::exit(EXIT_OK);
+} // find_directories
+
+/// Prints all entries whose path contains part, or just their idx if
+/// idx_only is set. Exits with EXIT_NOTFOUND if there is none.
+pub fn find_containing(db_name: &PathBuf, table: &str, part: &str, idx_only: bool) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let rows = db::search_dirs_containing(&conn, table, part);
+    let rows = check_and_unwrap(rows);
+    if rows.is_empty() {
+        process::exit(EXIT_NOTFOUND);
+    }
+    for row in rows {
+        if idx_only {
+            println!("{}", row.idx);
+        } else {
+            println!("{0:>4} {1} {2}", row.idx, row.alias, row.directory);
+        }
+    }
+    process::exit(EXIT_OK);
+} // find_containing
+
+/// Removes the rows from database corresponding to entries within one
+/// transaction. Entries which cannot be found are reported and skipped
+/// (a single entry fails as usual). Several entries are reported one per
+/// line followed by a summary. Just prints the rows if dry_run is set.
+pub fn remove_row(
+    db_name: &PathBuf,
+    table: &str,
+    entries: &[String],
+    dry_run: bool,
+    matching: &db::AliasMatching,
+) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let mut rows = Vec::<db::StdRow>::new();
+    let mut all_not_found = true;
+    for entry in entries {
+        match db::find_entry(&conn, table, &IdxAlias::from(entry.as_str()), matching) {
+            Ok(row) => rows.push(row),
+            Err(e) if entries.len() == 1 => exit_with_db_error(ErrorKind::General, &e),
+            Err(e) => {
+                eprintln!("{entry}: {e}");
+                all_not_found &= db::is_not_found(&e);
+            }
+        }
+    }
+    let found = rows.len();
+    rows.sort_by_key(|r| r.id);
+    rows.dedup_by_key(|r| r.id);
+
+    let verb = if dry_run {
+        "would be removed"
+    } else {
+        "removed"
+    };
+    if !dry_run {
+        let ids: Vec<u64> = rows.iter().map(|r| r.id.unwrap()).collect();
+        let res = db::rm_std_dirs(&conn, table, &ids);
+        check_and_unwrap(res);
+    }
+    if dry_run || entries.len() > 1 {
+        for row in &rows {
+            println!("{0:>4} {1} {2} {verb}", row.idx, row.alias, row.directory);
+        }
+    }
+    if entries.len() > 1 {
+        println!("{} of {} entries {verb}", rows.len(), entries.len());
+    }
+    process::exit(match (found == entries.len(), all_not_found) {
+        (true, _) => EXIT_OK,
+        (false, true) => EXIT_NOTFOUND,
+        (false, false) => EXIT_ERROR,
+    });
+} // remove_row
+
+/// Name of a backup of db_name with timestamp, e.g. '.qcd_rs.20240601-120000.sqlite'.
+fn backup_name(db_name: &Path, timestamp: &str) -> String {
+    let stem = db_name
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    match db_name.extension() {
+        Some(ext) => format!("{stem}.{timestamp}.{}", ext.to_string_lossy()),
+        None => format!("{stem}.{timestamp}"),
+    }
+} // backup_name
+
+/// Runs add/find/update/remove and stack push/pop against conn.
+/// Returns description of first failing step.
+fn run_self_test(conn: &Connection) -> Result<(), String> {
+    let table = db::MAINTABLENAME;
+    let sessionid = "selftest0selftest0selftest0";
+    let directory = Utf8PathBuf::from("/qcd/self/test");
+    let check = |ok: bool, step: &str| {
+        if ok {
+            Ok(())
+        } else {
+            Err(format!("Unexpected result: {step}"))
+        }
+    };
+
+    let row = db::StdRow {
+        id: None,
+        idx: 1,
+        directory: directory.clone(),
+        alias: "selftest".to_string(),
+    };
+    db::add_std_dir(conn, table, &row).map_err(|e| format!("add: {e}"))?;
+    let found = db::find_entry(
+        conn,
+        table,
+        &IdxAlias::Alias("self".to_string()),
+        &db::AliasMatching::default(),
+    )
+    .map_err(|e| format!("find: {e}"))?;
+    check(found.directory == directory, "find")?;
+    db::update_entry(conn, table, 1, &IdxAlias::Idx(2)).map_err(|e| format!("update: {e}"))?;
+    let found = db::search_dir(conn, table, &directory).map_err(|e| format!("find: {e}"))?;
+    check(found.idx == 2, "update")?;
+    db::rm_std_dir(conn, table, found.id.unwrap()).map_err(|e| format!("remove: {e}"))?;
+    check(
+        db::find_entry(
+            conn,
+            table,
+            &IdxAlias::Idx(2),
+            &db::AliasMatching::default(),
+        )
+        .is_err(),
+        "remove",
+    )?;
+
+    let stack = db::Stack::new(conn, sessionid, None);
+    stack
+        .push(&directory, None)
+        .map_err(|e| format!("push: {e}"))?;
+    let top = stack.pop().map_err(|e| format!("pop: {e}"))?;
+    check(top.directory == directory, "pop")?;
+    check(stack.peek().is_err(), "pop")?;
+    stack
+        .push(&directory, None)
+        .map_err(|e| format!("push: {e}"))?;
+    stack.clear().map_err(|e| format!("clear: {e}"))?;
+    check(
+        stack.rows().map_err(|e| format!("clear: {e}"))?.is_empty(),
+        "clear",
+    )?;
+
+    Ok(())
+} // run_self_test
+
+/// Checks database operations against an in-memory database,
+/// prints "OK" or the first failure.
+pub fn self_test() -> ! {
+    let conn = db::open_db(&PathBuf::from(":memory:"));
+    let conn = check_and_unwrap(conn);
+
+    match run_self_test(&conn) {
+        Ok(()) => {
+            println!("OK");
+            process::exit(EXIT_OK);
+        }
+        Err(e) => exit_with_error(ErrorKind::SelfTest, &e),
+    }
+} // self_test
+
+/// Prints CREATE statements of all tables and indexes.
+pub fn dump_schema(db_name: &PathBuf) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let schema = db::get_schema(&conn);
+    for sql in check_and_unwrap(schema) {
+        println!("{sql};");
+    }
+    process::exit(EXIT_OK);
+} // dump_schema
+
+/// Creates database (if not existing) with all tables.
+pub fn create_db(db_name: &PathBuf, auto_vacuum: bool) -> ! {
+    let existed = db_name.exists();
+    let conn = db::open_db_auto_vacuum(db_name, auto_vacuum);
+    check_and_unwrap(conn);
+    if existed {
+        println!("Database {} already exists", db_name.display());
+    } else {
+        println!("Database {} created", db_name.display());
+    }
+    process::exit(EXIT_OK);
+} // create_db
+
+/// Warns on stderr if QCD_RS_AUTO_VACUUM is set but the (existing)
+/// database does not use incremental auto_vacuum.
+pub fn warn_auto_vacuum(db_name: &PathBuf) {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+    if !check_and_unwrap(db::has_incremental_vacuum(&conn)) {
+        eprintln!(
+            "Warning: QCD_RS_AUTO_VACUUM is ignored, database {} was created without it",
+            db_name.display()
+        );
+    }
+} // warn_auto_vacuum
+
+/// Frees unused pages of a database with incremental auto_vacuum.
+pub fn incremental_vacuum(db_name: &PathBuf) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    if !check_and_unwrap(db::has_incremental_vacuum(&conn)) {
+        exit_with_error(
+            ErrorKind::General,
+            "Database does not use incremental auto_vacuum (see QCD_RS_AUTO_VACUUM)",
+        );
+    }
+    let pages = db::incremental_vacuum(&conn);
+    println!("{} pages freed", check_and_unwrap(pages));
+    process::exit(EXIT_OK);
+} // incremental_vacuum
+
+/// Parses a line 'idx<TAB>alias<TAB>path' or 'idx<TAB>path' of an import
+/// file. Blank lines and lines starting with '#' give None.
+fn parse_import_line(line: &str) -> Result<Option<db::StdRow>, String> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    if line.trim().is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let fields: Vec<&str> = line.split('\t').collect();
+    let (idx, alias, directory) = match fields[..] {
+        [idx, alias, directory] => (idx, alias, directory),
+        [idx, directory] => (idx, "", directory),
+        _ => return Err("Expected idx<TAB>alias<TAB>path".to_string()),
+    };
+    let idx = match idx.trim().parse::<u32>() {
+        Ok(i) => i,
+        Err(_) => return Err(format!("Invalid idx '{idx}'")),
+    };
+    if directory.is_empty() {
+        return Err("Missing path".to_string());
+    }
+    Ok(Some(db::StdRow {
+        id: None,
+        idx,
+        directory: Utf8PathBuf::from(directory),
+        alias: alias.to_string(),
+    }))
+} // parse_import_line
+
+/// Adds the entries of file ('-' for stdin, see parse_import_line) within
+/// one transaction. Invalid lines and entries whose idx or alias is in
+/// use are reported and skipped.
+pub fn import(db_name: &PathBuf, table: &str, file: &Utf8Path) -> ! {
+    let content = if file == "-" {
+        io::read_to_string(io::stdin())
+    } else {
+        fs::read_to_string(file)
+    };
+    let content = match content {
+        Ok(c) => c,
+        Err(e) => exit_with_error(ErrorKind::Path, &format!("Could not read {file}\n{e}")),
+    };
+
+    let mut skipped = 0;
+    let mut entries = Vec::new();
+    let mut lines = Vec::new();
+    for (n, line) in content.lines().enumerate() {
+        match parse_import_line(line) {
+            Ok(Some(e)) => {
+                entries.push(e);
+                lines.push(n + 1);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("Line {}: {e}", n + 1);
+                skipped += 1;
+            }
+        }
+    }
+
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let res = db::add_std_dirs(&conn, table, &entries);
+    let res = check_and_unwrap(res);
+    let mut added = 0;
+    for (n, r) in lines.iter().zip(res) {
+        match r {
+            Ok(_) => added += 1,
+            Err(e) => {
+                eprintln!("Line {n}: {e}");
+                skipped += 1;
+            }
         }
-        Ok(s) => s,
     }
-} // check_and_unwrap
+    println!("{added} entries added, {skipped} skipped");
+    process::exit(EXIT_OK);
+} // import
 
-/// Tries to get a unique representation of a path.
-fn clean_path(path: &Utf8PathBuf) -> Result<Utf8PathBuf, String> {
-    let new_path = path.as_std_path().absolutize();
-    match new_path {
-        Ok(pth) => match Utf8PathBuf::from_path_buf(pth.to_path_buf()) {
-            Ok(pth) => Ok(pth),
-            Err(_) => Err("Only UTF-8 paths supported".to_string()),
-        },
-        Err(e) => Err(format!("Could not get absolute path\n{e}")),
+/// Writes all entries to file ('-' for stdout) as lines
+/// 'idx<TAB>alias<TAB>path', which can be read by import. Entries whose
+/// alias or path contain tabs or line breaks are reported and skipped.
+pub fn export(db_name: &PathBuf, table: &str, file: &Utf8Path) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let entries = db::get_std_rows(&conn, table);
+    let entries = check_and_unwrap(entries);
+
+    let mut content = String::new();
+    for e in entries {
+        let fields = [e.alias.as_str(), e.directory.as_str()];
+        if fields.iter().any(|f| f.contains(['\t', '\n', '\r'])) {
+            eprintln!(
+                "Entry {} skipped, alias or path contains tab or line break",
+                e.idx
+            );
+            continue;
+        }
+        content.push_str(&format!("{}\t{}\t{}\n", e.idx, e.alias, e.directory));
     }
-} // clean_path
 
-/// Print directory associated with entry, push push_dir onto stack
-pub fn chdir(
+    if file == "-" {
+        print!("{content}");
+    } else if let Err(e) = fs::write(file, content) {
+        exit_with_error(ErrorKind::Path, &format!("Could not write {file}\n{e}"));
+    }
+    process::exit(EXIT_OK);
+} // export
+
+/// Copies the database to directory (default: directory of database),
+/// adding a timestamp to the file name.
+pub fn backup(db_name: &PathBuf, directory: Option<Utf8PathBuf>) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let timestamp = Local::now().format("%Y%m%d-%H%M%S").to_string();
+    let file_name = backup_name(db_name, &timestamp);
+    let target = match directory {
+        Some(d) => d.as_std_path().join(file_name),
+        None => db_name.with_file_name(file_name),
+    };
+
+    let res = db::backup_db(&conn, &target);
+    check_and_unwrap(res);
+    println!("Database copied to {}", target.display());
+    process::exit(EXIT_OK);
+} // backup
+
+/// Replaces the database by a copy of file. An existing database is
+/// backed up next to it first. The copy is written to a temporary file
+/// which is then renamed, so db_name is replaced atomically.
+pub fn replace_db(db_name: &PathBuf, file: &Path) -> ! {
+    if !file.is_file() {
+        exit_with_error(
+            ErrorKind::Path,
+            &format!("{} does not exist", file.display()),
+        );
+    }
+    let new_conn = db::open_db(&file.to_path_buf());
+    let new_conn = check_and_unwrap(new_conn);
+
+    if db_name.exists() {
+        let conn = db::open_db(db_name);
+        let conn = check_and_unwrap(conn);
+        let timestamp = Local::now().format("%Y%m%d-%H%M%S").to_string();
+        let target = db_name.with_file_name(backup_name(db_name, &timestamp));
+        let res = db::backup_db(&conn, &target);
+        check_and_unwrap(res);
+        println!("Old database copied to {}", target.display());
+    }
+
+    let mut tmp_name = db_name.as_os_str().to_owned();
+    tmp_name.push(format!(".tmp{}", process::id()));
+    let tmp = PathBuf::from(tmp_name);
+    let res = db::backup_db(&new_conn, &tmp);
+    check_and_unwrap(res);
+    if let Err(e) = fs::rename(&tmp, db_name) {
+        let _ = fs::remove_file(&tmp);
+        exit_with_error(
+            ErrorKind::General,
+            &format!("Could not replace database\n{e}"),
+        );
+    }
+    println!(
+        "Database {} replaced by {}",
+        db_name.display(),
+        file.display()
+    );
+    process::exit(EXIT_OK);
+} // replace_db
+
+/// Prints the directory for each entry, an empty line if an entry cannot be
+/// resolved. Exits with EXIT_NOTFOUND if any entry was not resolved.
+pub fn resolve_all(
     db_name: &PathBuf,
     table: &str,
-    entry: &str,
-    push_dir: Option<Utf8PathBuf>,
-    sessionid: &str,
+    entries: &[String],
+    matching: &db::AliasMatching,
 ) -> ! {
-    let row = get_single_row(db_name, table, entry);
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
 
-    if let Some(dir) = push_dir {
-        let _ = stack_push(db_name, sessionid, dir);
+    let mut all_found = true;
+    for entry in entries {
+        match find_entry_layered(&conn, db_name, table, &IdxAlias::from(entry), matching) {
+            Ok(row) => println!("{}", native_dir(&row.directory)),
+            Err(e) => {
+                eprintln!("{entry}: {e}");
+                println!();
+                all_found = false;
+            }
+        }
     }
+    process::exit(if all_found { EXIT_OK } else { EXIT_NOTFOUND });
+} // resolve_all
 
-    println!("{}", row.directory);
-    process::exit(0);
-} // chdir
+/// Options controlling print_row
+#[derive(Debug, Default)]
+pub struct EchoOptions {
+    /// Print idx, alias and directory as JSON object
+    pub json: bool,
+    /// Style the directory is converted to (see styled_path), native if None
+    pub style: Option<String>,
+    /// Accept directory of entry even if it does not exist
+    pub allow_missing: bool,
+    /// Record the visit of entry
+    pub touch: bool,
+    /// Settings of alias lookup
+    pub matching: db::AliasMatching,
+}
 
-/// Prints all entries of the specified table sorted by idx.
-pub fn list_dirs(db_name: &PathBuf, table: &str) -> ! {
+/// Prints a single directory name corresponding to entry. If json is
+/// set, idx, alias and directory are printed as JSON object. The directory
+/// is converted to style (see styled_path), if given. Paths which cannot
+/// be converted are printed unchanged. The visit is only recorded if
+/// touch is set.
+pub fn print_row(db_name: &PathBuf, table: &str, entry: &str, opts: &EchoOptions) -> ! {
+    let mut row = get_single_row(db_name, table, entry, false, &opts.matching);
+    check_exists(&row.directory, opts.allow_missing);
+    if opts.touch {
+        record_visit(db_name, table, &row);
+    }
+    let style = opts.style.as_deref().unwrap_or("native");
+    let directory = match styled_path(row.directory.as_str(), style) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("{e}, printed unchanged");
+            row.directory.to_string()
+        }
+    };
+    row.directory = Utf8PathBuf::from(directory);
+    if opts.json {
+        println!("{}", row_to_json(&row));
+    } else {
+        println!("{}", row.directory);
+    }
+    process::exit(EXIT_OK);
+} // print_row
+
+// Stack routines
+
+/// Print directories on stack top to bottom
+pub fn stack_list_dirs(db_name: &PathBuf, sessionid: &str, expire_days: Option<i64>) -> ! {
     let conn = db::open_db(db_name);
     let conn = check_and_unwrap(conn);
 
-    let entries = db::get_std_rows(&conn, table);
+    let entries = db::Stack::new(&conn, sessionid, expire_days).rows();
     let entries = check_and_unwrap(entries);
 
-    let alias_len = entries
-        .iter()
-        .fold(0, |m, e| cmp::max(m, e.alias.chars().count()));
-    for entry in entries {
-        println!(
-            "{0:>4} {1:<alias_len$} {2}",
-            entry.idx, entry.alias, entry.directory
-        );
+    for e in entries {
+        println!("{}", native_dir(&e.directory));
     }
-    process::exit(1);
-} // list_dirs
+    process::exit(EXIT_OK);
+} // stack_list_dirs
 
-/// Add one row to tables like 'main'
-pub fn add_row(
+/// Report the session id from QCD_RS_SESSIONID and whether it is valid
+/// for stack commands. Exits with EXIT_USAGE if it is not.
+pub fn verify_session(sessionid: &str, valid: bool) -> ! {
+    if sessionid.is_empty() {
+        println!("QCD_RS_SESSIONID is not set, stack commands are not available");
+        println!("Add 'export QCD_RS_SESSIONID=`qcd_rs --pid`' to your shell configuration");
+        process::exit(EXIT_USAGE);
+    }
+    let len = sessionid.chars().count();
+    if valid {
+        println!("QCD_RS_SESSIONID is valid ({len} characters), stack commands are available");
+        process::exit(EXIT_OK);
+    }
+    println!("QCD_RS_SESSIONID is too short ({len} characters), stack commands are not available");
+    println!("Set it with 'export QCD_RS_SESSIONID=`qcd_rs --pid`'");
+    process::exit(EXIT_USAGE);
+} // verify_session
+
+/// Print number of entries in table, or of entries on the stack of
+/// sessionid if given
+pub fn count(
     db_name: &PathBuf,
     table: &str,
-    idx: Option<u32>,
-    directory: Utf8PathBuf,
-    alias: Option<String>,
+    sessionid: Option<&str>,
+    expire_days: Option<i64>,
 ) -> ! {
     let conn = db::open_db(db_name);
     let conn = check_and_unwrap(conn);
 
-    let idx = match idx {
-        Some(i) => i,
-        None => {
-            let max_idx = db::get_max_idx(&conn, table);
-            let max_idx = check_and_unwrap(max_idx);
-            max_idx + 1
-        }
-    };
-    let alias = match alias {
-        Some(s) => s,
-        None => "".to_string(),
-    };
-    let clean_dir = clean_path(&directory);
-    let clean_dir = check_and_unwrap(clean_dir);
-    let entry = db::StdRow {
-        id: None,
-        idx,
-        directory: clean_dir,
-        alias,
+    let n = match sessionid {
+        Some(sid) => db::stack_depth(&conn, sid, expire_days).map(u64::from),
+        None => db::count_rows(&conn, table),
     };
-    let new_idx = db::add_std_dir(&conn, table, &entry);
-    let new_idx = check_and_unwrap(new_idx);
-    println!("Path added with index {new_idx}");
-    process::exit(1);
-} // add_row
+    let n = check_and_unwrap(n);
+
+    println!("{n}");
+    process::exit(EXIT_OK);
+} // count
 
-/// Set new idx or alias for row corresponding to idx
-pub fn update_row(db_name: &PathBuf, table: &str, idx: u32, entry: &IdxAlias) -> ! {
+/// Print number of visits of entry with idx, 0 if it was never visited
+pub fn print_visits(db_name: &PathBuf, table: &str, idx: u32) -> ! {
     let conn = db::open_db(db_name);
     let conn = check_and_unwrap(conn);
 
-    let res = db::update_entry(&conn, table, idx, entry);
-    check_and_unwrap(res);
-
-    process::exit(1);
-} // update_row
+    let row = match db::find_entry(&conn, table, &Idx(idx), &db::AliasMatching::default()) {
+        Ok(r) => r,
+        Err(e) => exit_with_db_error(ErrorKind::General, &e),
+    };
+    let visits = check_and_unwrap(db::get_visits(&conn, table));
+    let n = visits
+        .iter()
+        .find(|(id, _, _)| Some(*id) == row.id)
+        .map_or(0, |(_, n, _)| *n);
 
-/// Searches for the row corresponding to entry
-fn get_single_row(db_name: &PathBuf, table: &str, entry: &str) -> db::StdRow {
-    let entry = db::IdxAlias::from(entry);
+    println!("{n}");
+    process::exit(EXIT_OK);
+} // print_visits
 
+/// Print glyph followed by the number of entries on stack, nothing if
+/// stack is empty or sessionid is missing
+pub fn stack_prompt_status(
+    db_name: &PathBuf,
+    sessionid: Option<&str>,
+    expire_days: Option<i64>,
+) -> ! {
+    let Some(sessionid) = sessionid else {
+        process::exit(EXIT_OK);
+    };
     let conn = db::open_db(db_name);
     let conn = check_and_unwrap(conn);
-    let row = db::find_entry(&conn, table, &entry);
-    check_and_unwrap(row)
-} // get_single_row
 
-/// Searches for directory name, prints idx value if found, prints -1 otherwise
-pub fn find_directory(db_name: &PathBuf, table: &str, directory: Utf8PathBuf) -> ! {
-    let clean_dir = clean_path(&directory);
-    let clean_dir = check_and_unwrap(clean_dir);
+    let depth = db::stack_depth(&conn, sessionid, expire_days);
+    let depth = check_and_unwrap(depth);
 
+    if depth > 0 {
+        let glyph = env::var(PROMPTGLYPH_KEY).unwrap_or_else(|_| PROMPTGLYPH.to_string());
+        println!("{glyph}{depth}");
+    }
+    process::exit(EXIT_OK);
+} // stack_prompt_status
+
+/// Print directory at depth (0 = top) of stack without modifying the stack
+pub fn stack_at(db_name: &PathBuf, sessionid: &str, expire_days: Option<i64>, depth: usize) -> ! {
     let conn = db::open_db(db_name);
     let conn = check_and_unwrap(conn);
-    let row = db::search_dir(&conn, table, &clean_dir);
-    match row {
-        Ok(r) => {
-            println!("{}", r.idx);
-        }
-        Err(_) => {
-            println!("-1");
-        }
+
+    let entries = db::Stack::new(&conn, sessionid, expire_days).rows();
+    let entries = check_and_unwrap(entries);
+
+    match entries.get(depth) {
+        Some(e) => println!("{}", native_dir(&e.directory)),
+        None => exit_with_error(
+            ErrorKind::NotFound,
+            &format!("No entry at depth {depth}, stack holds {}", entries.len()),
+        ),
     }
-    process::exit(1);
-} // find_directory
+    process::exit(EXIT_OK);
+} // stack_at
 
-/// Removes one row from database corresponding to entry
-pub fn remove_row(db_name: &PathBuf, table: &str, entry: &str) -> ! {
-    let row = get_single_row(db_name, table, entry);
+/// Exits with EXIT_OK if directory is on stack, with EXIT_ERROR otherwise
+pub fn stack_contains(
+    db_name: &PathBuf,
+    sessionid: &str,
+    expire_days: Option<i64>,
+    directory: Utf8PathBuf,
+) -> ! {
+    let clean_dir = clean_path(&directory);
+    let clean_dir = check_and_unwrap(clean_dir);
 
     let conn = db::open_db(db_name);
     let conn = check_and_unwrap(conn);
-    let res = db::rm_std_dir(&conn, table, row.id.unwrap());
-    check_and_unwrap(res);
-    process::exit(1);
-} // remove_row
 
-/// Prints a single directory name corresponding to entry
-pub fn print_row(db_name: &PathBuf, table: &str, entry: &str) -> ! {
-    let row = get_single_row(db_name, table, entry);
-    println!("{}", row.directory);
-    process::exit(1);
-} // print_row
+    let entries = db::Stack::new(&conn, sessionid, expire_days).rows();
+    let entries = check_and_unwrap(entries);
 
-// Stack routines
+    if entries.iter().any(|e| e.directory == clean_dir) {
+        process::exit(EXIT_OK);
+    }
+    process::exit(EXIT_ERROR);
+} // stack_contains
 
-/// Print directories on stack top to bottom
-pub fn stack_list_dirs(db_name: &PathBuf, sessionid: &str) -> ! {
+/// Print 'qcd -u PATH' commands restoring the stack, bottom entry first
+pub fn stack_export_script(db_name: &PathBuf, sessionid: &str, expire_days: Option<i64>) -> ! {
     let conn = db::open_db(db_name);
     let conn = check_and_unwrap(conn);
 
-    let entries = db::get_stack_rows(&conn, sessionid);
+    let entries = db::Stack::new(&conn, sessionid, expire_days).rows();
     let entries = check_and_unwrap(entries);
 
-    for e in entries {
-        println!("{}", e.directory);
+    for e in entries.iter().rev() {
+        println!("qcd -u {}", shell_quote(&native_dir(&e.directory)));
     }
-    process::exit(1);
-} // stack_list_dirs
+    process::exit(EXIT_OK);
+} // stack_export_script
 
-/// Add directory to top of stack but prevent duplication on top
+/// Add directory (with optional note) to top of stack but prevent
+/// duplication on top unless force is set. The oldest entries beyond
+/// max_depth are dropped.
 pub fn stack_push(
     db_name: &PathBuf,
     sessionid: &str,
+    expire_days: Option<i64>,
+    max_depth: Option<u32>,
     directory: Utf8PathBuf,
+    note: Option<&str>,
+    force: bool,
 ) -> Result<(), String> {
     let clean_dir = clean_path(&directory)?;
     let conn = db::open_db(db_name)?;
 
+    let stack = db::Stack::new(&conn, sessionid, expire_days).with_max_depth(max_depth);
+
     // Prevent duplicates on top of stack
-    let top_entry = db::stack_top(&conn, sessionid);
-    if let Ok(row) = top_entry {
-        if clean_dir == row.directory {
-            return Ok(());
-        }
+    if force {
+        stack.push(&clean_dir, note)?;
+    } else {
+        stack.push_unless_top(&clean_dir, note)?;
     }
-
-    let entry = db::StackRow {
-        id: None,
-        sessionid: sessionid.to_owned(),
-        directory: clean_dir,
-    };
-
-    db::add_stack_dir(&conn, &entry)?;
     Ok(())
 } // stack_push
 
-/// Print top of stack after removing corresponding row
-pub fn stack_pop(db_name: &PathBuf, sessionid: &str) -> ! {
+/// Print top of stack without removing it. Fails like stack_pop on an
+/// empty stack.
+pub fn stack_print_top(db_name: &PathBuf, sessionid: &str, expire_days: Option<i64>) -> ! {
     let conn = db::open_db(db_name);
     let conn = check_and_unwrap(conn);
 
-    let entry = db::stack_pop(&conn, sessionid);
+    let entry = db::Stack::new(&conn, sessionid, expire_days).peek();
     match entry {
         Ok(e) => {
-            println!("{}", e.directory);
-            process::exit(0);
+            println!("{}", native_dir(&e.directory));
+            process::exit(EXIT_OK);
         }
-        Err(e) => {
-            println!("{e}");
+        Err(e) => exit_with_db_error(ErrorKind::Stack, &e),
+    }
+} // stack_print_top
+
+/// Print top of stack without removing it. An empty stack is reported
+/// on stderr, so nothing is printed to stdout (e.g. in a prompt).
+pub fn stack_peek(db_name: &PathBuf, sessionid: &str, expire_days: Option<i64>) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let entry = db::Stack::new(&conn, sessionid, expire_days).peek();
+    match entry {
+        Ok(e) => {
+            println!("{}", native_dir(&e.directory));
+            process::exit(EXIT_OK);
+        }
+        Err(e) if db::is_not_found(&e) && !json_errors() => {
+            eprintln!("{e}");
+            process::exit(EXIT_NOTFOUND);
+        }
+        Err(e) => exit_with_db_error(ErrorKind::Stack, &e),
+    }
+} // stack_peek
+
+/// Print top of stack after removing corresponding row. The row is
+/// kept if dry_run is set. A note stored with the entry goes to stderr.
+pub fn stack_pop(db_name: &PathBuf, sessionid: &str, expire_days: Option<i64>, dry_run: bool) -> ! {
+    if dry_run {
+        stack_print_top(db_name, sessionid, expire_days);
+    }
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let entry = db::Stack::new(&conn, sessionid, expire_days).pop();
+    match entry {
+        Ok(e) => {
+            if let Some(note) = &e.label {
+                eprintln!("[note: {note}]");
+            }
+            println!("{}", native_dir(&e.directory));
+            process::exit(EXIT_OK);
         }
+        Err(e) => exit_with_db_error(ErrorKind::Stack, &e),
     }
-    process::exit(1);
 } // stack_pop
 
-/// Remove top entry on stack
-pub fn stack_drop(db_name: &PathBuf, sessionid: &str) -> ! {
+/// Resolve entry, pop stack until its directory is popped and print it.
+/// The stack stays unchanged if the directory is not on the stack.
+pub fn stack_pop_to(
+    db_name: &PathBuf,
+    table: &str,
+    sessionid: &str,
+    expire_days: Option<i64>,
+    entry: &str,
+    matching: &db::AliasMatching,
+) -> ! {
+    let row = get_single_row(db_name, table, entry, false, matching);
+
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let entry = db::Stack::new(&conn, sessionid, expire_days).pop_to(&row.directory);
+    match entry {
+        Ok(e) => {
+            println!("{}", native_dir(&e.directory));
+            process::exit(EXIT_OK);
+        }
+        Err(e) => exit_with_db_error(ErrorKind::Stack, &e),
+    }
+} // stack_pop_to
+
+/// Exchange entries at depths a and b (0 = top) of stack
+pub fn stack_swap_depths(
+    db_name: &PathBuf,
+    sessionid: &str,
+    expire_days: Option<i64>,
+    a: usize,
+    b: usize,
+) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let res = db::Stack::new(&conn, sessionid, expire_days).swap(a, b);
+    if let Err(e) = res {
+        exit_with_error(ErrorKind::Stack, &e);
+    }
+    process::exit(EXIT_OK);
+} // stack_swap_depths
+
+/// Remove top entry on stack. Just prints it if dry_run is set.
+pub fn stack_drop(
+    db_name: &PathBuf,
+    sessionid: &str,
+    expire_days: Option<i64>,
+    dry_run: bool,
+) -> ! {
     let conn = db::open_db(db_name);
     let conn = check_and_unwrap(conn);
 
-    let entry = db::stack_pop(&conn, sessionid);
+    let stack = db::Stack::new(&conn, sessionid, expire_days);
+    if dry_run {
+        match stack.peek() {
+            Ok(e) => println!("{} would be dropped", native_dir(&e.directory)),
+            Err(e) => exit_with_db_error(ErrorKind::Stack, &e),
+        }
+        process::exit(EXIT_OK);
+    }
+    let entry = stack.pop();
     if let Err(e) = entry {
-        println!("{e}");
+        exit_with_db_error(ErrorKind::Stack, &e);
     }
-    process::exit(1);
+    process::exit(EXIT_OK);
 } // stack_drop
 
+/// Remove all entries of the session from the stack
+pub fn stack_clear(db_name: &PathBuf, sessionid: &str, expire_days: Option<i64>) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let n = db::Stack::new(&conn, sessionid, expire_days).clear();
+    let n = check_and_unwrap(n);
+
+    println!("{n} entries removed from stack");
+    process::exit(EXIT_OK);
+} // stack_clear
+
+/// Move bottom of stack to top and print it.
+pub fn stack_cycle(db_name: &PathBuf, sessionid: &str, expire_days: Option<i64>) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let entry = db::Stack::new(&conn, sessionid, expire_days).rotate();
+    match entry {
+        Ok(e) => {
+            println!("{}", native_dir(&e.directory));
+            process::exit(EXIT_OK);
+        }
+        Err(e) => exit_with_db_error(ErrorKind::Stack, &e),
+    }
+} // stack_cycle
+
+/// Add all directories on stack to table, oldest first. Directories
+/// already contained in table are skipped.
+pub fn stack_to_main(
+    db_name: &PathBuf,
+    table: &str,
+    sessionid: &str,
+    expire_days: Option<i64>,
+) -> ! {
+    let conn = db::open_db(db_name);
+    let conn = check_and_unwrap(conn);
+
+    let entries = db::Stack::new(&conn, sessionid, expire_days).rows();
+    let entries = check_and_unwrap(entries);
+
+    let directories: Vec<Utf8PathBuf> = entries.into_iter().rev().map(|e| e.directory).collect();
+    let added = db::add_std_dirs_next_idx(&conn, table, &directories);
+    let added = check_and_unwrap(added);
+    println!("{added} paths added");
+    process::exit(EXIT_OK);
+} // stack_to_main
+
 /// Print top of stack after removing it. Push directory.
-pub fn stack_swap(db_name: &PathBuf, sessionid: &str, directory: Utf8PathBuf) -> ! {
+/// Only prints top of stack if dry_run is set.
+pub fn stack_swap(
+    db_name: &PathBuf,
+    sessionid: &str,
+    expire_days: Option<i64>,
+    max_depth: Option<u32>,
+    directory: Utf8PathBuf,
+    dry_run: bool,
+) -> ! {
+    if dry_run {
+        stack_print_top(db_name, sessionid, expire_days);
+    }
     let conn = db::open_db(db_name);
     let conn = check_and_unwrap(conn);
 
-    let entry = db::stack_pop(&conn, sessionid);
+    let entry = db::Stack::new(&conn, sessionid, expire_days).pop();
     if let Err(e) = entry {
-        println!("{e}");
-        process::exit(1);
+        exit_with_db_error(ErrorKind::Stack, &e);
     }
     let entry = entry.unwrap();
 
-    let res = stack_push(db_name, sessionid, directory);
+    let res = stack_push(
+        db_name,
+        sessionid,
+        expire_days,
+        max_depth,
+        directory,
+        None,
+        false,
+    );
     if let Err(e) = res {
-        println!("{e}");
-        process::exit(1);
+        exit_with_error(ErrorKind::Stack, &e);
     }
 
-    println!("{}", entry.directory);
-    process::exit(0);
+    println!("{}", native_dir(&entry.directory));
+    process::exit(EXIT_OK);
 } // stack_swap
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_json() {
+        assert_eq!(json_escape("plain"), "plain");
+        assert_eq!(json_escape("a\"b\\c"), "a\\\"b\\\\c");
+        assert_eq!(json_escape("line\nnext\t"), "line\\nnext\\t");
+        assert_eq!(json_escape("\u{1}"), "\\u0001");
+    } // escape_json
+
+    #[test]
+    fn kinds_of_errors() {
+        assert_eq!(ErrorKind::NotFound.as_str(), "not_found");
+        assert_eq!(ErrorKind::NotFound.exit_code(), EXIT_NOTFOUND);
+        assert_eq!(ErrorKind::Session.exit_code(), EXIT_USAGE);
+        assert_eq!(ErrorKind::Stack.exit_code(), EXIT_ERROR);
+    } // kinds_of_errors
+
+    #[test]
+    fn quote_for_shell() {
+        assert_eq!(shell_quote("/home/me/work"), "/home/me/work");
+        assert_eq!(shell_quote("/home/me/my work"), "'/home/me/my work'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote(""), "''");
+    } // quote_for_shell
+
+    #[test]
+    fn menu_selection() {
+        let find = |line: &str| match line {
+            "1" | "work" => Ok("/home/work"),
+            _ => Err(format!("{line} not found")),
+        };
+        let mut input = "work\n".as_bytes();
+        assert_eq!(read_selection(&mut input, find), Some("/home/work"));
+        // Invalid and empty selections prompt again
+        let mut input = "7\n\n  1 \nwork\n".as_bytes();
+        assert_eq!(read_selection(&mut input, find), Some("/home/work"));
+        assert_eq!(input, "work\n".as_bytes());
+        let mut input = "7\nnope\n".as_bytes();
+        assert_eq!(read_selection(&mut input, find), None);
+        let mut input = "".as_bytes();
+        assert_eq!(read_selection(&mut input, find), None);
+    } // menu_selection
+
+    #[test]
+    fn names_of_env_vars() {
+        assert_eq!(env_var_name("QCD_", "work"), Some("QCD_WORK".to_string()));
+        assert_eq!(
+            env_var_name("QCD_", "my-proj.v2"),
+            Some("QCD_MY_PROJ_V2".to_string())
+        );
+        assert_eq!(env_var_name("", "2nd"), None);
+        assert_eq!(env_var_name("", "work"), Some("WORK".to_string()));
+        assert_eq!(env_var_name("QCD_", "b\u{e4}r"), None);
+        assert_eq!(env_var_name("QCD_", "a$b"), None);
+    } // names_of_env_vars
+
+    #[test]
+    fn windows_prefixed_paths() {
+        assert_eq!(
+            normalize_windows_prefixed(r"\\?\C:\Users\me\"),
+            Some(r"\\?\C:\Users\me".to_string())
+        );
+        assert_eq!(
+            normalize_windows_prefixed(r"\\?\C:\"),
+            Some(r"\\?\C:\".to_string())
+        );
+        assert_eq!(
+            normalize_windows_prefixed(r"\\?\UNC\server\share\a"),
+            Some(r"\\?\UNC\server\share\a".to_string())
+        );
+        assert_eq!(
+            normalize_windows_prefixed(r"\\server\share\proj\"),
+            Some(r"\\server\share\proj".to_string())
+        );
+        assert_eq!(
+            normalize_windows_prefixed("//server/share/a/./b/../c"),
+            Some(r"\\server\share\a\c".to_string())
+        );
+        assert_eq!(
+            normalize_windows_prefixed(r"\\server\share\..\.."),
+            Some(r"\\server\share".to_string())
+        );
+        assert_eq!(normalize_windows_prefixed(r"\\server"), None);
+        assert_eq!(normalize_windows_prefixed(r"C:\Users"), None);
+        assert_eq!(normalize_windows_prefixed("/home/me"), None);
+    } // windows_prefixed_paths
+
+    #[test]
+    fn separators_round_trip() {
+        let stored_on_windows = unify_separators(r"C:\proj\sub");
+        let queried_on_unix = unify_separators("C:/proj/sub");
+        assert_eq!(stored_on_windows, "C:/proj/sub");
+        assert_eq!(stored_on_windows, queried_on_unix);
+
+        let stored_on_unix = unify_separators("/home/me/proj");
+        assert_eq!(stored_on_unix, "/home/me/proj");
+        let native = to_native_separators(&stored_on_windows);
+        if cfg!(windows) {
+            assert_eq!(native, r"C:\proj\sub");
+        } else {
+            assert_eq!(native, "C:/proj/sub");
+        }
+        assert_eq!(unify_separators(&native), stored_on_windows);
+    } // separators_round_trip
+
+    #[test]
+    fn rewrite_prefixes() {
+        let entries: Vec<db::StdRow> = ["/old/root", "/old/root/sub", "/old/rootish", "/other"]
+            .iter()
+            .enumerate()
+            .map(|(i, d)| db::StdRow {
+                id: Some(i as u64 + 1),
+                idx: i as u32 + 1,
+                directory: Utf8PathBuf::from(d),
+                alias: "".to_string(),
+            })
+            .collect();
+        let changes = rewritten_dirs(
+            entries,
+            Utf8Path::new("/old/root"),
+            Utf8Path::new("/new/root"),
+        );
+        let res: Vec<(u32, &str)> = changes.iter().map(|(e, d)| (e.idx, d.as_str())).collect();
+        assert_eq!(res, vec![(1, "/new/root"), (2, "/new/root/sub")]);
+    } // rewrite_prefixes
+
+    #[test]
+    fn duplicate_idxs() {
+        let entries: Vec<db::StdRow> = [(1, 2), (2, 2), (3, 5), (4, 2), (5, 3)]
+            .iter()
+            .map(|(id, idx)| db::StdRow {
+                id: Some(*id),
+                idx: *idx,
+                directory: Utf8PathBuf::from(format!("/dir/{id}")),
+                alias: "".to_string(),
+            })
+            .collect();
+        let changes = reassigned_idxs(entries);
+        let res: Vec<(u64, u32)> = changes.iter().map(|(e, i)| (e.id.unwrap(), *i)).collect();
+        assert_eq!(res, vec![(2, 6), (1, 7)]);
+        assert!(reassigned_idxs(Vec::new()).is_empty());
+    } // duplicate_idxs
+
+    #[test]
+    fn directory_tree() {
+        let rows = |dirs: &[&str]| -> Vec<db::StdRow> {
+            dirs.iter()
+                .enumerate()
+                .map(|(i, d)| db::StdRow {
+                    id: None,
+                    idx: i as u32 + 1,
+                    directory: Utf8PathBuf::from(d),
+                    alias: if i == 0 {
+                        "a".to_string()
+                    } else {
+                        "".to_string()
+                    },
+                })
+                .collect()
+        };
+        let tree = dir_tree(&rows(&[
+            "/home/me/x",
+            "/home/me/y/z",
+            "/home/me",
+            "/tmp",
+            "rel/p",
+        ]));
+        assert_eq!(
+            tree,
+            vec![
+                "/home/me [3]",
+                "  x [1 a]",
+                "  y/z [2]",
+                "/tmp [4]",
+                "rel/p [5]"
+            ]
+        );
+        // No common structure: flat list
+        let tree = dir_tree(&rows(&["/usr", "/etc/ssh", "/"]));
+        assert_eq!(tree, vec!["/ [3]", "/etc/ssh [2]", "/usr [1 a]"]);
+    } // directory_tree
+
+    #[test]
+    fn path_styles() {
+        assert_eq!(
+            styled_path("C:\\proj\\x", "posix"),
+            Ok("C:/proj/x".to_string())
+        );
+        assert_eq!(
+            styled_path("C:\\proj\\x", "wsl"),
+            Ok("/mnt/c/proj/x".to_string())
+        );
+        assert_eq!(styled_path("d:/", "wsl"), Ok("/mnt/d".to_string()));
+        let res = styled_path("\\\\?\\E:\\data", "wsl");
+        assert_eq!(res, Ok("/mnt/e/data".to_string()));
+        assert!(styled_path("/home/me", "wsl").is_err());
+        assert!(styled_path("\\\\server\\share", "wsl").is_err());
+        assert!(styled_path("C:proj", "wsl").is_err());
+    } // path_styles
+
+    #[test]
+    fn names_of_backups() {
+        let name = backup_name(Path::new("/home/me/.qcd_rs.sqlite"), "20240601-120000");
+        assert_eq!(name, ".qcd_rs.20240601-120000.sqlite");
+        let name = backup_name(Path::new("bookmarks"), "20240601-120000");
+        assert_eq!(name, "bookmarks.20240601-120000");
+    } // names_of_backups
+
+    #[test]
+    fn lexical_paths() {
+        let norm = |p: &str| lexical_normalize(Utf8Path::new(p)).to_string();
+        assert_eq!(norm("a/b/../c"), "a/c");
+        assert_eq!(norm("./a/./b/"), "a/b");
+        assert_eq!(norm("../a/../../b"), "../../b");
+        assert_eq!(norm("a/.."), ".");
+        assert_eq!(norm("/a/b/../../.."), "/");
+        assert_eq!(norm("/x/../y/./z"), "/y/z");
+    } // lexical_paths
+
+    #[test]
+    fn import_lines() {
+        let row = |idx: u32, alias: &str, dir: &str| {
+            Ok(Some(db::StdRow {
+                id: None,
+                idx,
+                directory: Utf8PathBuf::from(dir),
+                alias: alias.to_string(),
+            }))
+        };
+        assert_eq!(
+            parse_import_line("3\tproj\t/home/me/proj"),
+            row(3, "proj", "/home/me/proj")
+        );
+        assert_eq!(parse_import_line("4\t\t/tmp\r"), row(4, "", "/tmp"));
+        assert_eq!(parse_import_line("5\t/my dir"), row(5, "", "/my dir"));
+        assert_eq!(parse_import_line(""), Ok(None));
+        assert_eq!(parse_import_line("  "), Ok(None));
+        assert_eq!(parse_import_line("# 1\ta\t/a"), Ok(None));
+        assert!(parse_import_line("x\ta\t/a").is_err());
+        assert!(parse_import_line("1\ta\t").is_err());
+        assert!(parse_import_line("/just/a/path").is_err());
+    } // import_lines
+
+    #[test]
+    fn tilde_paths() {
+        let home = Utf8PathBuf::from_path_buf(simple_home_dir::home_dir().unwrap()).unwrap();
+        let clean = |p: &str| clean_path(&Utf8PathBuf::from(p)).unwrap();
+        assert!(clean("~/foo").starts_with(&home));
+        assert_eq!(clean("~/foo"), home.join("foo"));
+        assert_eq!(clean("~"), home);
+        assert_eq!(
+            clean("/tmp/dir~backup"),
+            Utf8PathBuf::from("/tmp/dir~backup")
+        );
+        assert_eq!(
+            expand_tilde(Utf8Path::new("a/~")),
+            Ok(Utf8PathBuf::from("a/~"))
+        );
+        assert!(expand_tilde(Utf8Path::new("~no_such_user_qcd/x")).is_err());
+        if cfg!(unix) {
+            assert_eq!(clean("~root/x"), user_home("root").unwrap().join("x"));
+        }
+    } // tilde_paths
+
+    #[test]
+    fn aliases_used_twice() {
+        let aliases = [
+            ("b".to_string(), 3),
+            ("a".to_string(), 1),
+            ("".to_string(), 4),
+            ("b".to_string(), 2),
+            ("".to_string(), 5),
+            ("c".to_string(), 6),
+            ("b".to_string(), 7),
+        ];
+        let dups = duplicate_aliases(&aliases);
+        assert_eq!(dups, vec![("b".to_string(), vec![2, 3, 7])]);
+        assert!(duplicate_aliases(&aliases[..3]).is_empty());
+    } // aliases_used_twice
+
+    #[test]
+    fn time_spans() {
+        assert_eq!(parse_ttl("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_ttl("12h"), Ok(Duration::from_secs(12 * 3600)));
+        assert_eq!(parse_ttl("14d"), Ok(Duration::from_secs(14 * 86400)));
+        assert_eq!(parse_ttl("2w"), Ok(Duration::from_secs(14 * 86400)));
+        assert!(parse_ttl("14").is_err());
+        assert!(parse_ttl("d").is_err());
+        assert!(parse_ttl("0d").is_err());
+        assert!(parse_ttl("-1d").is_err());
+        assert!(parse_ttl("").is_err());
+        assert!(parse_ttl("99999999999999999w").is_err());
+        assert!(parse_ttl(&format!("{}m", u64::MAX)).is_err());
+        assert!(parse_ttl(&format!("{}m", i64::MAX)).is_err());
+        let max = format!("{}m", i64::MAX as u64 / 60);
+        assert!(parse_ttl(&max).is_ok());
+
+        let now = 1_700_000_000;
+        assert_eq!(
+            expiry_timestamp(now, Duration::from_secs(60)),
+            Some(now + 60)
+        );
+        assert_eq!(expiry_timestamp(now, parse_ttl(&max).unwrap()), None);
+        assert_eq!(expiry_timestamp(now, Duration::from_secs(u64::MAX)), None);
+    } // time_spans
+
+    #[test]
+    fn rows_as_json() {
+        let entry = db::StdRow {
+            id: Some(3),
+            idx: 7,
+            directory: Utf8PathBuf::from("/a \"b\""),
+            alias: "".to_string(),
+        };
+        assert_eq!(
+            row_to_json(&entry),
+            "{\"idx\":7,\"alias\":\"\",\"directory\":\"/a \\\"b\\\"\"}"
+        );
+    } // rows_as_json
+
+    #[test]
+    fn parent_directories() {
+        let dir = Utf8Path::new("/usr/share");
+        assert_eq!(parent_dir(dir, 0), Ok(Utf8PathBuf::from("/usr/share")));
+        assert_eq!(parent_dir(dir, 1), Ok(Utf8PathBuf::from("/usr")));
+        assert_eq!(parent_dir(dir, 2), Ok(Utf8PathBuf::from("/")));
+        assert!(parent_dir(dir, 3).is_err());
+        assert!(parent_dir(Utf8Path::new("/nosuchdir/sub"), 1).is_err());
+    } // parent_directories
+
+    #[test]
+    fn formatted_times() {
+        let now = 1_700_000_000;
+        let fmt = |ts: i64| format_time(ts, now, None, None).unwrap();
+        assert_eq!(fmt(now + 30), "now");
+        assert_eq!(fmt(now + 60), "in 1 minute");
+        assert_eq!(fmt(now - 2 * 3600), "2 hours ago");
+        assert_eq!(fmt(now + 3 * 86400 + 5), "in 3 days");
+
+        let res = format_time(now, now, Some("%Y-%m-%d %H:%M"), Some("UTC"));
+        assert_eq!(res, Ok("2023-11-14 22:13".to_string()));
+        let res = format_time(now, now, Some("%H:%M %z"), Some("+02:00"));
+        assert_eq!(res, Ok("00:13 +0200".to_string()));
+        assert!(format_time(now, now, Some("%Q"), None).is_err());
+        assert!(format_time(now, now, Some("%H"), Some("Mars")).is_err());
+    } // formatted_times
+
+    #[test]
+    fn frecency_scores() {
+        let now = 1_700_000_000;
+        let day = 24 * 3600;
+        assert!(frecency(5, now - 600, now) > frecency(20, now - 30 * day, now));
+        assert!(frecency(5, now - 2 * 3600, now) > frecency(5, now - 2 * day, now));
+        assert!(frecency(2, now, now) > frecency(1, now, now));
+        assert_eq!(frecency(0, now, now), 0.0);
+    } // frecency_scores
+
+    #[test]
+    fn self_test_in_memory() {
+        let conn = db::open_db(&PathBuf::from(":memory:")).unwrap();
+        assert_eq!(run_self_test(&conn), Ok(()));
+    } // self_test_in_memory
+
+    #[test]
+    fn padded_aliases() {
+        assert_eq!(pad_alias("ab", 4, ' '), "ab  ");
+        assert_eq!(pad_alias("ab", 4, '.'), "ab..");
+        assert_eq!(pad_alias("äö", 4, '.'), "äö..");
+        assert_eq!(pad_alias("abcd", 2, '.'), "abcd");
+    } // padded_aliases
+
+    #[test]
+    fn wide_aliases_aligned() {
+        let width = cmp::max("文档".width(), "doc".width());
+        assert_eq!(width, 4);
+        assert_eq!(pad_alias("文档", width, ' '), "文档");
+        assert_eq!(pad_alias("doc", width, ' '), "doc ");
+        assert_eq!(pad_alias("doc", width, ' ').width(), "文档".width());
+    } // wide_aliases_aligned
+
+    #[test]
+    fn changes_against_git_index() {
+        let dir = Utf8PathBuf::from_path_buf(env::temp_dir().join("qcd_rs_index_test")).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "abc").unwrap();
+        let mtime = fs::metadata(dir.join("a.txt"))
+            .unwrap()
+            .modified()
+            .unwrap()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32;
+
+        // Version 2 index with a single entry
+        let index = |path: &str, mtime: u32, size: u32| {
+            let mut data = b"DIRC".to_vec();
+            data.extend(2u32.to_be_bytes());
+            data.extend(1u32.to_be_bytes());
+            let mut entry = vec![0u8; 62];
+            entry[8..12].copy_from_slice(&mtime.to_be_bytes());
+            entry[24..28].copy_from_slice(&0o100644u32.to_be_bytes());
+            entry[36..40].copy_from_slice(&size.to_be_bytes());
+            entry[60..62].copy_from_slice(&(path.len() as u16).to_be_bytes());
+            entry.extend(path.as_bytes());
+            entry.resize((62 + path.len() + 8) & !7, 0);
+            data.extend(entry);
+            data
+        };
+        assert_eq!(index_differs(&dir, &index("a.txt", mtime, 3)), Some(false));
+        assert_eq!(index_differs(&dir, &index("a.txt", mtime, 4)), Some(true));
+        assert_eq!(
+            index_differs(&dir, &index("a.txt", mtime - 1, 3)),
+            Some(true)
+        );
+        assert_eq!(
+            index_differs(&dir, &index("gone.txt", mtime, 3)),
+            Some(true)
+        );
+        assert_eq!(index_differs(&dir, b"no index"), None);
+
+        let _ = fs::remove_dir_all(&dir);
+    } // changes_against_git_index
+} // mod tests
@@ -1,14 +1,28 @@
 use camino::{Utf8Path, Utf8PathBuf};
 use chrono::{DateTime, Duration, Utc};
-use rusqlite::Connection;
 use rusqlite::Error::InvalidColumnType;
-use std::path::PathBuf;
+use rusqlite::{Connection, DatabaseName, OpenFlags, Transaction, TransactionBehavior};
+use std::cmp;
+use std::path::{Path, PathBuf};
 
 use crate::db::IdxAlias::{Alias, Idx};
 
 pub const MAINTABLENAME: &str = "main";
 pub const STACKTABLENAME: &str = "_stack";
+const ALIASTABLENAME: &str = "_aliases";
 const STACKEXPIRE_DAYS: i64 = 21;
+const MAXCANDIDATES: usize = 10;
+const AUTOVACUUM_INCREMENTAL: i64 = 2;
+/// Schema version written to PRAGMA user_version, see MIGRATIONS
+const SCHEMA_VERSION: u32 = 3;
+
+const ENTRY_NOT_FOUND: &str = "Entry not contained in table";
+const ALIAS_NOT_FOUND: &str = "Alias not found in table";
+const STACK_EMPTY: &str = "Nothing on stack";
+/// Condition on rows of tables like 'main' which have not expired yet.
+/// Reads skip expired rows, only write paths remove them (see tidyup_main).
+const NOT_EXPIRED: &str =
+    "(expires_at IS NULL OR expires_at > CAST(strftime('%s', 'now') AS integer))";
 
 #[derive(Debug, PartialEq)]
 pub struct StdRow {
@@ -23,6 +37,7 @@ pub struct StackRow {
     pub id: Option<u64>,
     pub sessionid: String,
     pub directory: Utf8PathBuf,
+    pub label: Option<String>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -31,6 +46,24 @@ pub enum IdxAlias {
     Alias(String),
 }
 
+/// Checks if err is the result of an unsuccessful search for
+/// an entry or an empty stack.
+pub fn is_not_found(err: &str) -> bool {
+    err == ENTRY_NOT_FOUND || err == ALIAS_NOT_FOUND || err == STACK_EMPTY
+} // is_not_found
+
+/// Checks that alias can be matched by alias lookup. IdxAlias::from
+/// takes every string which parses as u32 for an idx, so a numeric
+/// alias like '42' could never be found by its alias.
+pub fn check_alias(alias: &str) -> Result<(), String> {
+    match IdxAlias::from(alias) {
+        Idx(_) => Err(format!(
+            "Alias '{alias}' is numeric and would always be taken as idx"
+        )),
+        Alias(_) => Ok(()),
+    }
+} // check_alias
+
 impl IdxAlias {
     /// Create an Idx if entry can be parsed as u32 otherwise create an Alias.
     pub fn from(entry: &str) -> Self {
@@ -54,11 +87,41 @@ impl IdxAlias {
     }
 }
 
+/// Settings of the alias lookup of find_entry
+#[derive(Debug, Clone)]
+pub struct AliasMatching {
+    /// Maximum number of candidates listed for an ambiguous alias
+    pub max_candidates: usize,
+    /// Try unique subsequences (e.g. 'wrk' for 'work') if no alias matches
+    pub subsequence: bool,
+    /// Separator of alias components, aliases continuing with it after the
+    /// query are reported as group (e.g. 'proj' for 'proj.a', 'proj.b')
+    pub separator: Option<String>,
+}
+
+impl Default for AliasMatching {
+    fn default() -> Self {
+        AliasMatching {
+            max_candidates: MAXCANDIDATES,
+            subsequence: false,
+            separator: None,
+        }
+    }
+}
+
 /// Opens the database.
 ///
 /// The database with the specified name is opened (or created).
 /// If tables main and/or stack do not exist they are created.
+/// Before an existing database is migrated to a newer schema version,
+/// a copy is written next to it (see backup_before_migration).
 pub fn open_db(db_name: &PathBuf) -> Result<Connection, String> {
+    open_db_auto_vacuum(db_name, false)
+} // open_db
+
+/// Opens the database like open_db. If the database gets created and
+/// auto_vacuum is set, it uses incremental auto_vacuum.
+pub fn open_db_auto_vacuum(db_name: &PathBuf, auto_vacuum: bool) -> Result<Connection, String> {
     let conn_res = Connection::open(db_name);
 
     let conn = match conn_res {
@@ -67,13 +130,33 @@ pub fn open_db(db_name: &PathBuf) -> Result<Connection, String> {
             return Err(format!("Could not open database\n{e}"));
         }
     };
+    let fresh = !has_table(&conn, MAINTABLENAME)?;
+    // auto_vacuum can only be changed before the first table is created
+    if auto_vacuum && fresh {
+        if let Err(e) = conn.pragma_update(None, "auto_vacuum", "INCREMENTAL") {
+            return Err(format!("Could not set auto_vacuum\n{e}"));
+        }
+    }
+    let version = schema_version(&conn)?;
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "Database has schema version {version}, this qcd supports up to {SCHEMA_VERSION}.\n\
+             Please update qcd"
+        ));
+    }
+    if !fresh && version < SCHEMA_VERSION {
+        backup_before_migration(&conn, db_name, version)?;
+    }
     if let Err(e) = conn.execute(
         &format!(
             "create table if not exists {} (
              id integer primary key,
              idx integer,
              directory text not null,
-             alias text
+             alias text,
+             expires_at integer,
+             visits integer not null default 0,
+             last_access integer
          )",
             MAINTABLENAME
         ),
@@ -87,7 +170,8 @@ pub fn open_db(db_name: &PathBuf) -> Result<Connection, String> {
             id integer primary key,
             sessionid text not null,
             timestamp integer not null,
-            directory text not null
+            directory text not null,
+            label text
         )",
             STACKTABLENAME
         ),
@@ -95,48 +179,410 @@ pub fn open_db(db_name: &PathBuf) -> Result<Connection, String> {
     ) {
         return Err(format!("Could not create stack table\n{e}"));
     }
+    if let Err(e) = conn.execute(
+        &format!(
+            "create table if not exists {} (
+            id integer primary key,
+            entry_id integer not null,
+            alias text not null unique
+        )",
+            ALIASTABLENAME
+        ),
+        (),
+    ) {
+        return Err(format!("Could not create alias table\n{e}"));
+    }
+    migrate(&conn, version)?;
 
     Ok(conn)
-} // open_db
+} // open_db_auto_vacuum
+
+/// Opens an existing database read-only.
+///
+/// Unlike open_db neither tables are created nor the schema is migrated,
+/// hence the database file is never changed. Fails if table main lacks
+/// columns of the current schema that reads depend on.
+pub fn open_db_readonly(db_name: &Path) -> Result<Connection, String> {
+    let conn = Connection::open_with_flags(db_name, OpenFlags::SQLITE_OPEN_READ_ONLY);
+    let conn = match conn {
+        Ok(c) => c,
+        Err(e) => {
+            return Err(format!(
+                "Could not open database {}\n{e}",
+                db_name.display()
+            ));
+        }
+    };
+    let version = schema_version(&conn)?;
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "Database {} has schema version {version}, this qcd supports up to {SCHEMA_VERSION}.\n\
+             Please update qcd",
+            db_name.display()
+        ));
+    }
+    if !has_table(&conn, MAINTABLENAME)? {
+        return Err(format!(
+            "Database {} contains no table {MAINTABLENAME}",
+            db_name.display()
+        ));
+    }
+    if !has_column(&conn, MAINTABLENAME, "expires_at")? {
+        return Err(format!(
+            "Database {} has an outdated schema, use it once as primary database to migrate it",
+            db_name.display()
+        ));
+    }
+    Ok(conn)
+} // open_db_readonly
+
+type Migration = fn(&Connection) -> Result<(), String>;
+
+/// Migrations of the schema. Entry i upgrades a database of version i to
+/// version i + 1. Tables are created in their current form before, hence
+/// migrations have to cope with tables that are up to date already.
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] =
+    [add_expires_at, add_visits, add_stack_label];
+
+/// Version of the schema as stored in PRAGMA user_version (0 if never set).
+fn schema_version(conn: &Connection) -> Result<u32, String> {
+    let res = conn.pragma_query_value(None, "user_version", |row| row.get::<usize, u32>(0));
+    match res {
+        Ok(v) => Ok(v),
+        Err(e) => Err(format!("Could not query schema version\n{e}")),
+    }
+} // schema_version
+
+/// Copies the database to '<db_name>.bak-<version>' and reports the path
+/// on stderr. An existing copy of that version is kept, so the backup
+/// is only made once.
+fn backup_before_migration(conn: &Connection, db_name: &Path, version: u32) -> Result<(), String> {
+    let mut target = db_name.as_os_str().to_owned();
+    target.push(format!(".bak-{version}"));
+    let target = PathBuf::from(target);
+    if target.exists() {
+        return Ok(());
+    }
+    backup_db(conn, &target)?;
+    eprintln!(
+        "Backup of database (schema version {version}) written to {}",
+        target.display()
+    );
+    Ok(())
+} // backup_before_migration
+
+/// Runs the migrations from version up to SCHEMA_VERSION, each one
+/// within a transaction together with the update of user_version.
+fn migrate(conn: &Connection, version: u32) -> Result<(), String> {
+    for v in version..SCHEMA_VERSION {
+        let tx = conn.unchecked_transaction();
+        if let Err(e) = tx {
+            return Err(format!("Could not start transaction\n{e}"));
+        }
+        let tx = tx.unwrap();
+
+        MIGRATIONS[v as usize](&tx)?;
+        if let Err(e) = tx.pragma_update(None, "user_version", v + 1) {
+            return Err(format!("Could not set schema version\n{e}"));
+        }
+        if let Err(e) = tx.commit() {
+            return Err(format!(
+                "Could not migrate to schema version {}\n{e}",
+                v + 1
+            ));
+        }
+    }
+    Ok(())
+} // migrate
+
+/// Version 1: main tables created before entries could expire
+fn add_expires_at(conn: &Connection) -> Result<(), String> {
+    if has_column(conn, MAINTABLENAME, "expires_at")? {
+        return Ok(());
+    }
+    if let Err(e) = conn.execute(
+        &format!(
+            "ALTER TABLE {} ADD COLUMN expires_at integer",
+            MAINTABLENAME
+        ),
+        (),
+    ) {
+        return Err(format!("Could not add column expires_at\n{e}"));
+    }
+    Ok(())
+} // add_expires_at
+
+/// Version 2: count visits of entries
+fn add_visits(conn: &Connection) -> Result<(), String> {
+    for (column, decl) in [
+        ("visits", "integer not null default 0"),
+        ("last_access", "integer"),
+    ] {
+        if has_column(conn, MAINTABLENAME, column)? {
+            continue;
+        }
+        if let Err(e) = conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {column} {decl}", MAINTABLENAME),
+            (),
+        ) {
+            return Err(format!("Could not add column {column}\n{e}"));
+        }
+    }
+    Ok(())
+} // add_visits
+
+/// Version 3: notes attached to stack entries
+fn add_stack_label(conn: &Connection) -> Result<(), String> {
+    if has_column(conn, STACKTABLENAME, "label")? {
+        return Ok(());
+    }
+    if let Err(e) = conn.execute(
+        &format!("ALTER TABLE {} ADD COLUMN label text", STACKTABLENAME),
+        (),
+    ) {
+        return Err(format!("Could not add column label\n{e}"));
+    }
+    Ok(())
+} // add_stack_label
+
+/// Runs f within an immediate transaction, such that checks and writes
+/// of f cannot interleave with other processes. If conn is within a
+/// transaction already, f just becomes part of it.
+fn immediate_transaction<T>(
+    conn: &Connection,
+    f: impl FnOnce(&Connection) -> Result<T, String>,
+) -> Result<T, String> {
+    if !conn.is_autocommit() {
+        return f(conn);
+    }
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate);
+    if let Err(e) = tx {
+        return Err(format!("Could not start transaction\n{e}"));
+    }
+    let tx = tx.unwrap();
+
+    let res = f(&tx)?;
+    if let Err(e) = tx.commit() {
+        return Err(format!("Could not commit changes\n{e}"));
+    }
+    Ok(res)
+} // immediate_transaction
+
+/// Checks if a table named table exists.
+fn has_table(conn: &Connection, table: &str) -> Result<bool, String> {
+    let res = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name=?1",
+        [table],
+        |row| row.get::<usize, u32>(0),
+    );
+    match res {
+        Ok(n) => Ok(n != 0),
+        Err(e) => Err(format!("Could not query tables\n{e}")),
+    }
+} // has_table
+
+/// True if database uses incremental auto_vacuum.
+pub fn has_incremental_vacuum(conn: &Connection) -> Result<bool, String> {
+    let res = conn.pragma_query_value(None, "auto_vacuum", |row| row.get::<usize, i64>(0));
+    match res {
+        Ok(mode) => Ok(mode == AUTOVACUUM_INCREMENTAL),
+        Err(e) => Err(format!("Could not query auto_vacuum\n{e}")),
+    }
+} // has_incremental_vacuum
+
+/// Removes all free pages from the database file. Returns the number
+/// of removed pages.
+pub fn incremental_vacuum(conn: &Connection) -> Result<u32, String> {
+    let free_pages =
+        || conn.pragma_query_value(None, "freelist_count", |row| row.get::<usize, u32>(0));
+    let before = match free_pages() {
+        Ok(n) => n,
+        Err(e) => return Err(format!("Could not query free pages\n{e}")),
+    };
+    if let Err(e) = conn.execute_batch("PRAGMA incremental_vacuum") {
+        return Err(format!("Could not vacuum database\n{e}"));
+    }
+    match free_pages() {
+        Ok(n) => Ok(before.saturating_sub(n)),
+        Err(e) => Err(format!("Could not query free pages\n{e}")),
+    }
+} // incremental_vacuum
+
+/// Checks if table has a column named column.
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool, String> {
+    let res = conn.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name=?2",
+        [table, column],
+        |row| row.get::<usize, u32>(0),
+    );
+    match res {
+        Ok(n) => Ok(n != 0),
+        Err(e) => Err(format!("Could not query columns of table\n{e}")),
+    }
+} // has_column
+
+/// Writes a consistent copy of the database to target.
+pub fn backup_db(conn: &Connection, target: &Path) -> Result<(), String> {
+    if let Err(e) = conn.backup(DatabaseName::Main, target, None) {
+        return Err(format!("Could not backup database\n{e}"));
+    }
+    Ok(())
+} // backup_db
+
+/// Query CREATE statements of tables 'main', '_stack' and '_aliases'
+/// and their indexes.
+pub fn get_schema(conn: &Connection) -> Result<Vec<String>, String> {
+    let stmt = conn.prepare(
+        "SELECT sql FROM sqlite_master WHERE tbl_name IN (?1, ?2, ?3) AND sql IS NOT NULL
+         ORDER BY tbl_name, type DESC, name",
+    );
+    if let Err(e) = stmt {
+        return Err(format!("Could not prepare schema query statement\n{e}"));
+    }
+
+    let mut stmt = stmt.unwrap();
+    let rows = stmt.query_map([MAINTABLENAME, STACKTABLENAME, ALIASTABLENAME], |row| {
+        row.get::<usize, String>(0)
+    });
+    if let Err(e) = rows {
+        return Err(format!("Could not query schema\n{e}"));
+    }
+    Ok(rows.unwrap().flatten().collect())
+} // get_schema
 
 /// Add one row to tables like 'main'.
 pub fn add_std_dir(conn: &Connection, table: &str, entry: &StdRow) -> Result<u32, String> {
-    match contains_idx(conn, table, entry.idx) {
-        Ok(b) => {
-            if b {
-                return Err("Idx already exists!".to_string());
+    insert_std_dir(conn, table, entry, None, false)
+} // add_std_dir
+
+/// Add one row to tables like 'main' with the next free idx (highest idx
+/// plus one, entry.idx is ignored). Returns the new idx.
+pub fn add_std_dir_next_idx(conn: &Connection, table: &str, entry: &StdRow) -> Result<u32, String> {
+    insert_std_dir(conn, table, entry, None, true)
+} // add_std_dir_next_idx
+
+/// Adds directories not yet contained in table with the next free idx
+/// values, in the given order and within one transaction. Returns the
+/// number of added rows.
+pub fn add_std_dirs_next_idx(
+    conn: &Connection,
+    table: &str,
+    directories: &[Utf8PathBuf],
+) -> Result<usize, String> {
+    immediate_transaction(conn, |conn| {
+        let mut added = 0;
+        for directory in directories {
+            if search_dir(conn, table, directory).is_ok() {
+                continue;
             }
+            let entry = StdRow {
+                id: None,
+                idx: 0,
+                directory: directory.clone(),
+                alias: "".to_string(),
+            };
+            insert_std_dir(conn, table, &entry, None, true)?;
+            added += 1;
         }
-        Err(e) => {
-            return Err(format!("When checking if idx exists\n{e}"));
-        }
-    }
-    if !entry.alias.is_empty() {
-        match contains_alias(conn, table, &entry.alias) {
+        Ok(added)
+    })
+} // add_std_dirs_next_idx
+
+/// Add one row to tables like 'main', which is removed after expires_at
+/// (seconds since epoch) if given. With next_idx set, the row gets the
+/// next free idx instead of entry.idx, determined within the same
+/// transaction. Returns the idx of the row.
+fn insert_std_dir(
+    conn: &Connection,
+    table: &str,
+    entry: &StdRow,
+    expires_at: Option<i64>,
+    next_idx: bool,
+) -> Result<u32, String> {
+    immediate_transaction(conn, |conn| {
+        let _ = tidyup_main(conn, table);
+
+        let idx = if next_idx {
+            get_max_idx(conn, table)? + 1
+        } else {
+            entry.idx
+        };
+        match contains_idx(conn, table, idx) {
             Ok(b) => {
                 if b {
-                    return Err("Alias already exists!".to_string());
+                    return Err("Idx already exists!".to_string());
                 }
             }
             Err(e) => {
-                return Err(format!("When checking if alias exists\n{e}"));
+                return Err(format!("When checking if idx exists\n{e}"));
+            }
+        }
+        if !entry.alias.is_empty() {
+            match contains_alias(conn, table, &entry.alias) {
+                Ok(b) => {
+                    if b {
+                        return Err("Alias already exists!".to_string());
+                    }
+                }
+                Err(e) => {
+                    return Err(format!("When checking if alias exists\n{e}"));
+                }
             }
         }
-    }
 
-    let res = conn.execute(
-        &format!(
-            "INSERT INTO {} (idx, directory, alias) values (?1, ?2, ?3)",
-            table
-        ),
-        rusqlite::params![entry.idx, entry.directory.as_str(), entry.alias],
-    );
-    if let Err(e) = res {
-        return Err(format!("Could not add row to table\n{e}"));
-    }
+        let res = conn.execute(
+            &format!(
+                "INSERT INTO {} (idx, directory, alias, expires_at) values (?1, ?2, ?3, ?4)",
+                table
+            ),
+            rusqlite::params![idx, entry.directory.as_str(), entry.alias, expires_at],
+        );
+        if let Err(e) = res {
+            return Err(format!("Could not add row to table\n{e}"));
+        }
 
-    Ok(entry.idx)
-} // add_std_dir
+        Ok(idx)
+    })
+} // insert_std_dir
+
+/// Adds entries within one transaction. Entries which cannot be added
+/// (e.g. because idx or alias are in use) are skipped, the result
+/// holds the outcome of add_std_dir for each entry.
+pub fn add_std_dirs(
+    conn: &Connection,
+    table: &str,
+    entries: &[StdRow],
+) -> Result<Vec<Result<u32, String>>, String> {
+    immediate_transaction(conn, |conn| {
+        Ok(entries
+            .iter()
+            .map(|e| add_std_dir(conn, table, e))
+            .collect())
+    })
+} // add_std_dirs
+
+/// Adds entry together with additional aliases extra within one
+/// transaction. Nothing is added if any of the aliases is in use.
+/// The entry is removed after expires_at (seconds since epoch) if given.
+/// With next_idx set, the entry gets the next free idx (see insert_std_dir).
+pub fn add_std_dir_with_aliases(
+    conn: &Connection,
+    table: &str,
+    entry: &StdRow,
+    extra: &[String],
+    expires_at: Option<i64>,
+    next_idx: bool,
+) -> Result<u32, String> {
+    immediate_transaction(conn, |conn| {
+        let idx = insert_std_dir(conn, table, entry, expires_at, next_idx)?;
+        for alias in extra {
+            if let Err(e) = add_alias(conn, table, idx, alias) {
+                return Err(format!("Alias '{alias}': {e}"));
+            }
+        }
+        Ok(idx)
+    })
+} // add_std_dir_with_aliases
 
 /// Removes row with unique id (not idx!)
 pub fn rm_std_dir(conn: &Connection, table: &str, id: u64) -> Result<(), String> {
@@ -151,9 +597,36 @@ pub fn rm_std_dir(conn: &Connection, table: &str, id: u64) -> Result<(), String>
         return Err(format!("Could not delete row\n{e}"));
     }
 
+    let res = conn.execute(
+        &format!("DELETE FROM {} WHERE entry_id=?1", ALIASTABLENAME),
+        [id],
+    );
+    if let Err(e) = res {
+        return Err(format!("Could not delete additional aliases of row\n{e}"));
+    }
+
     Ok(())
 } // rm_std_dir
 
+/// Removes the rows given by their id (and their additional aliases)
+/// within one transaction.
+pub fn rm_std_dirs(conn: &Connection, table: &str, ids: &[u64]) -> Result<(), String> {
+    let tx = conn.unchecked_transaction();
+    if let Err(e) = tx {
+        return Err(format!("Could not start transaction\n{e}"));
+    }
+    let tx = tx.unwrap();
+
+    for id in ids {
+        rm_std_dir(&tx, table, *id)?;
+    }
+
+    if let Err(e) = tx.commit() {
+        return Err(format!("Could not commit changes\n{e}"));
+    }
+    Ok(())
+} // rm_std_dirs
+
 /// Returns the largest value found in column 'idx' for the specified table.
 pub fn get_max_idx(conn: &Connection, table: &str) -> Result<u32, String> {
     let stmt = conn.prepare(&format!("SELECT max(idx) FROM {}", table));
@@ -172,6 +645,19 @@ pub fn get_max_idx(conn: &Connection, table: &str) -> Result<u32, String> {
     Ok(res.unwrap())
 } // get_max_idx
 
+/// Number of (not expired) rows in table, 0 for an empty table.
+pub fn count_rows(conn: &Connection, table: &str) -> Result<u64, String> {
+    let res = conn.query_row(
+        &format!("SELECT COUNT(*) FROM {} WHERE {}", table, NOT_EXPIRED),
+        [],
+        |row| row.get::<usize, u64>(0),
+    );
+    match res {
+        Ok(n) => Ok(n),
+        Err(e) => Err(format!("Could not count rows of table\n{e}")),
+    }
+} // count_rows
+
 /// Checks if idx can be found in table.
 pub fn contains_idx(conn: &Connection, table: &str, idx: u32) -> Result<bool, String> {
     let stmt = conn.prepare(&format!(
@@ -192,11 +678,12 @@ pub fn contains_idx(conn: &Connection, table: &str, idx: u32) -> Result<bool, St
     Ok(res.unwrap() != 0)
 } // contains_idx
 
-/// Checks if alias can be found in table.
+/// Checks if alias can be found in table or among the additional aliases.
 pub fn contains_alias(conn: &Connection, table: &str, alias: &str) -> Result<bool, String> {
     let stmt = conn.prepare(&format!(
-        "SELECT EXISTS(SELECT 1 FROM {} WHERE alias=?1)",
-        table
+        "SELECT EXISTS(SELECT 1 FROM {} WHERE alias=?1)
+             OR EXISTS(SELECT 1 FROM {} WHERE alias=?1)",
+        table, ALIASTABLENAME
     ));
     if let Err(e) = stmt {
         return Err(format!(
@@ -212,9 +699,21 @@ pub fn contains_alias(conn: &Connection, table: &str, alias: &str) -> Result<boo
     Ok(res.unwrap() != 0)
 } // contains_alias
 
-/// Query all entries in tables like 'main'. Resulting Vec is sorted by idx.
+/// Text in column idx of row. NULL or invalid values (e.g. in databases
+/// edited externally) result in an empty string.
+fn text_or_empty(row: &rusqlite::Row, idx: usize) -> String {
+    row.get::<usize, Option<String>>(idx)
+        .unwrap_or_default()
+        .unwrap_or_default()
+} // text_or_empty
+
+/// Query all (not expired) entries in tables like 'main'. Resulting Vec
+/// is sorted by idx.
 pub fn get_std_rows(conn: &Connection, table: &str) -> Result<Vec<StdRow>, String> {
-    let stmt = conn.prepare(&format!("SELECT * FROM {} ORDER BY idx", table));
+    let stmt = conn.prepare(&format!(
+        "SELECT * FROM {} WHERE {} ORDER BY idx",
+        table, NOT_EXPIRED
+    ));
     if let Err(e) = stmt {
         return Err(format!("Could not prepare row query statement\n{e}"));
     }
@@ -224,8 +723,8 @@ pub fn get_std_rows(conn: &Connection, table: &str) -> Result<Vec<StdRow>, Strin
         Ok((
             row.get::<usize, u64>(0)?,
             row.get::<usize, u32>(1)?,
-            row.get::<usize, String>(2)?,
-            row.get::<usize, String>(3)?,
+            text_or_empty(row, 2),
+            text_or_empty(row, 3),
         ))
     });
     if let Err(e) = rows {
@@ -246,7 +745,7 @@ pub fn get_std_rows(conn: &Connection, table: &str) -> Result<Vec<StdRow>, Strin
     Ok(entries)
 } // get_std_rows
 
-/// Search for an entry in specified column.
+/// Search for a (not expired) entry in specified column.
 fn query_entry(
     conn: &Connection,
     table: &str,
@@ -254,8 +753,8 @@ fn query_entry(
     query: &str,
 ) -> Result<StdRow, String> {
     let stmt = conn.prepare(&format!(
-        "SELECT * FROM {} WHERE {}=?1 LIMIT 1",
-        table, col_name
+        "SELECT * FROM {} WHERE {}=?1 AND {} LIMIT 1",
+        table, col_name, NOT_EXPIRED
     ));
     if let Err(e) = stmt {
         return Err(format!("Could not prepare find statement\n{e}"));
@@ -266,8 +765,8 @@ fn query_entry(
         Ok((
             row.get::<usize, u64>(0)?,
             row.get::<usize, u32>(1)?,
-            row.get::<usize, String>(2)?,
-            row.get::<usize, String>(3)?,
+            text_or_empty(row, 2),
+            text_or_empty(row, 3),
         ))
     });
     if let Err(e) = rows {
@@ -284,12 +783,58 @@ fn query_entry(
         };
         return Ok(entry);
     }
-    Err("Entry not contained in table".to_string())
+    Err(ENTRY_NOT_FOUND.to_string())
 } // query_entry
 
-/// Search for alias like "name*". Succeed only if query is unique.
-fn query_alias_fuzzy(conn: &Connection, table: &str, alias: &str) -> Result<StdRow, String> {
-    let stmt = conn.prepare(&format!("SELECT * FROM {} WHERE alias like ?1", table));
+/// Query all rows whose directory contains part (case-insensitive for
+/// ASCII letters, '%' and '_' are taken literally). Sorted by idx.
+pub fn search_dirs_containing(
+    conn: &Connection,
+    table: &str,
+    part: &str,
+) -> Result<Vec<StdRow>, String> {
+    let stmt = conn.prepare(&format!(
+        "SELECT * FROM {} WHERE directory LIKE '%' || ?1 || '%' ESCAPE '\\' AND {} ORDER BY idx",
+        table, NOT_EXPIRED
+    ));
+    if let Err(e) = stmt {
+        return Err(format!("Could not prepare find statement\n{e}"));
+    }
+
+    let mut stmt = stmt.unwrap();
+    let pattern = part
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    let rows = stmt.query_map([pattern], |row| {
+        Ok(StdRow {
+            id: Some(row.get::<usize, u64>(0)?),
+            idx: row.get::<usize, u32>(1)?,
+            directory: Utf8PathBuf::from(text_or_empty(row, 2)),
+            alias: text_or_empty(row, 3),
+        })
+    });
+    if let Err(e) = rows {
+        return Err(format!("Could not query entries for searching\n{e}"));
+    }
+    Ok(rows.unwrap().flatten().collect())
+} // search_dirs_containing
+
+/// Search for alias like "name*" among the aliases in table and the
+/// additional aliases. Succeed only if query is unique.
+fn query_alias_fuzzy(
+    conn: &Connection,
+    table: &str,
+    alias: &str,
+    matching: &AliasMatching,
+) -> Result<StdRow, String> {
+    let stmt = conn.prepare(&format!(
+        "SELECT id, idx, directory, alias, alias FROM {0} WHERE alias like ?1 AND {2}
+         UNION ALL
+         SELECT m.id, m.idx, m.directory, m.alias, a.alias FROM {1} a
+             JOIN {0} m ON a.entry_id=m.id WHERE a.alias like ?1 AND {2}",
+        table, ALIASTABLENAME, NOT_EXPIRED
+    ));
     if let Err(e) = stmt {
         return Err(format!("Could not prepare find statement\n{e}"));
     }
@@ -299,8 +844,9 @@ fn query_alias_fuzzy(conn: &Connection, table: &str, alias: &str) -> Result<StdR
         Ok((
             row.get::<usize, u64>(0)?,
             row.get::<usize, u32>(1)?,
-            row.get::<usize, String>(2)?,
-            row.get::<usize, String>(3)?,
+            text_or_empty(row, 2),
+            text_or_empty(row, 3),
+            text_or_empty(row, 4),
         ))
     });
     if let Err(e) = rows {
@@ -308,126 +854,702 @@ fn query_alias_fuzzy(conn: &Connection, table: &str, alias: &str) -> Result<StdR
     }
     let rows = rows.unwrap();
 
-    let mut entry = StdRow {
-        id: None,
-        idx: 0,
-        directory: Utf8PathBuf::from(""),
-        alias: "".to_string(),
-    };
-    let mut count = 0;
+    let mut candidates = Vec::<(StdRow, String)>::new();
     for r in rows.flatten() {
-        entry = StdRow {
+        let entry = StdRow {
             id: Some(r.0),
             idx: r.1,
             directory: Utf8PathBuf::from(r.2),
             alias: r.3,
         };
-        if entry.alias == alias {
+        if r.4 == alias {
             return Ok(entry);
         }
-        count += 1;
-    }
-    if count == 1 {
-        return Ok(entry);
+        candidates.push((entry, r.4));
     }
-    if count > 1 {
-        return Err("Ambiguous alias specification".to_string());
+    if let Some(sep) = &matching.separator {
+        let names: Vec<String> = candidates.iter().map(|c| c.1.clone()).collect();
+        let group = alias_group(&names, alias, sep);
+        if !group.is_empty() {
+            return Err(format!(
+                "Alias group {alias}: {}",
+                format_candidates(&group, matching.max_candidates)
+            ));
+        }
     }
-    Err("Alias not found in table".to_string())
+    unique_candidate(candidates, matching.max_candidates)
 } // query_alias_fuzzy
 
-/// Search for an entry where either the idx or the alias is specified
-pub fn find_entry(conn: &Connection, table: &str, entry: &IdxAlias) -> Result<StdRow, String> {
-    let (col_name, query) = entry.to_colname_query();
-    if entry.is_alias() {
-        query_alias_fuzzy(conn, table, &query)
+/// Returns the (sorted) names which continue with sep right after prefix,
+/// i.e. prefix ends at a component boundary of them. Like the LIKE
+/// operator, ASCII case of prefix is ignored.
+fn alias_group(names: &[String], prefix: &str, sep: &str) -> Vec<String> {
+    let mut group: Vec<String> = names
+        .iter()
+        .filter(|n| {
+            n.get(..prefix.len())
+                .is_some_and(|p| p.eq_ignore_ascii_case(prefix))
+                && n[prefix.len()..].starts_with(sep)
+        })
+        .cloned()
+        .collect();
+    group.sort();
+    group.dedup();
+    group
+} // alias_group
+
+/// Returns the entry if all candidates refer to the same row, reports
+/// (at most max of) the matching aliases as ambiguous otherwise.
+fn unique_candidate(mut candidates: Vec<(StdRow, String)>, max: usize) -> Result<StdRow, String> {
+    candidates.sort_by_key(|c| c.0.id);
+    candidates.dedup_by(|a, b| a.0.id == b.0.id);
+    if candidates.len() == 1 {
+        return Ok(candidates.pop().unwrap().0);
+    }
+    if candidates.len() > 1 {
+        let mut aliases: Vec<String> = candidates.into_iter().map(|c| c.1).collect();
+        aliases.sort();
+        return Err(format!(
+            "Ambiguous alias specification: {}",
+            format_candidates(&aliases, max)
+        ));
+    }
+    Err(ALIAS_NOT_FOUND.to_string())
+} // unique_candidate
+
+/// Maximum number of candidates listed for an ambiguous alias, parsed
+/// from the value of QCD_RS_MAX_CANDIDATES. Defaults to MAXCANDIDATES if
+/// unset or unparseable.
+pub fn max_candidates(val: Option<&str>) -> usize {
+    match val {
+        Some(val) => val.parse::<usize>().unwrap_or(MAXCANDIDATES),
+        None => MAXCANDIDATES,
+    }
+} // max_candidates
+
+/// Comma separated list of the first max candidates plus a note
+/// about the number of omitted ones.
+fn format_candidates(candidates: &[String], max: usize) -> String {
+    let shown = candidates
+        .iter()
+        .take(max)
+        .cloned()
+        .collect::<Vec<String>>()
+        .join(", ");
+    if candidates.len() <= max {
+        return shown;
+    }
+    let more = format!("(and {} more)", candidates.len() - max);
+    if shown.is_empty() {
+        more
     } else {
-        query_entry(conn, table, &col_name, &query)
+        format!("{shown} {more}")
     }
-} // find_entry
-
-/// Search for a particular directory name
-pub fn search_dir(conn: &Connection, table: &str, directory: &Utf8Path) -> Result<StdRow, String> {
-    query_entry(conn, table, "directory", directory.as_str())
-} // search_dir
+} // format_candidates
+
+/// Checks if the characters of needle appear in hay in the same order.
+/// Ignores ASCII case like the LIKE operator does.
+fn is_subsequence(needle: &str, hay: &str) -> bool {
+    let mut hay = hay.chars().map(|c| c.to_ascii_lowercase());
+    needle
+        .chars()
+        .map(|c| c.to_ascii_lowercase())
+        .all(|n| hay.any(|h| h == n))
+} // is_subsequence
+
+/// Search for alias containing the characters of alias in the same order
+/// (e.g. 'wrk' matches 'work'). Succeed only if query is unique.
+fn query_alias_subsequence(
+    conn: &Connection,
+    table: &str,
+    alias: &str,
+    matching: &AliasMatching,
+) -> Result<StdRow, String> {
+    let extra_aliases = get_aliases(conn)?;
+    let mut candidates = Vec::<(StdRow, String)>::new();
+    for e in get_std_rows(conn, table)? {
+        let mut names: Vec<String> = extra_aliases
+            .iter()
+            .filter(|(id, _)| Some(*id) == e.id)
+            .map(|(_, a)| a.clone())
+            .collect();
+        names.push(e.alias.clone());
+        let name = names
+            .into_iter()
+            .find(|a| !a.is_empty() && is_subsequence(alias, a));
+        if let Some(name) = name {
+            candidates.push((e, name));
+        }
+    }
+    unique_candidate(candidates, matching.max_candidates)
+} // query_alias_subsequence
 
-/// Sets new idx or alias for row corresponding to idx
-pub fn update_entry(
+/// Search for an entry where either the idx or the alias is specified
+///
+/// Aliases match exactly or by unique prefix. If subsequence matching is
+/// selected in matching, unique subsequences are tried as well.
+pub fn find_entry(
     conn: &Connection,
     table: &str,
-    idx: u32,
     entry: &IdxAlias,
-) -> Result<(), String> {
-    let row = find_entry(conn, table, &Idx(idx))?;
-
-    // Check if there is nothing to do and prevent duplicating values
-    match entry {
-        Idx(i) => {
-            if i == &row.idx {
-                return Ok(());
-            }
-            if contains_idx(conn, table, *i)? {
-                return Err("Idx already contained in table".to_string());
-            }
-        }
-        Alias(s) => {
-            if s == &row.alias {
-                return Ok(());
-            }
-            if contains_alias(conn, table, s)? {
-                return Err("Alias already contained in table".to_string());
+    matching: &AliasMatching,
+) -> Result<StdRow, String> {
+    let (col_name, query) = entry.to_colname_query();
+    if entry.is_alias() {
+        let res = query_alias_fuzzy(conn, table, &query, matching);
+        match res {
+            Err(e) if e == ALIAS_NOT_FOUND && matching.subsequence => {
+                query_alias_subsequence(conn, table, &query, matching)
             }
+            _ => res,
         }
+    } else {
+        query_entry(conn, table, &col_name, &query)
     }
+} // find_entry
 
-    let (col_name, new_value) = entry.to_colname_query();
-    let stmt = conn.prepare(&format!("UPDATE {} SET {}=?1 WHERE id=?2", table, col_name));
+/// Query expiry times of all (not yet expired) entries that expire as
+/// pairs of row id and seconds since epoch.
+pub fn get_expiries(conn: &Connection, table: &str) -> Result<Vec<(u64, i64)>, String> {
+    let stmt = conn.prepare(&format!(
+        "SELECT id, expires_at FROM {} WHERE expires_at IS NOT NULL AND {}",
+        table, NOT_EXPIRED
+    ));
     if let Err(e) = stmt {
-        return Err(format!("Could not prepare update statement\n{e}"));
+        return Err(format!("Could not prepare expiry query statement\n{e}"));
     }
 
     let mut stmt = stmt.unwrap();
-    let res = stmt.execute(rusqlite::params![new_value, row.id]);
-    if let Err(e) = res {
-        return Err(format!("Could not update row\n{e}"));
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<usize, u64>(0)?, row.get::<usize, i64>(1)?))
+    });
+    if let Err(e) = rows {
+        return Err(format!("Could not query expiry times\n{e}"));
     }
+    Ok(rows.unwrap().flatten().collect())
+} // get_expiries
 
+/// Increments the number of visits of row id and sets its last access to now.
+pub fn record_visit(conn: &Connection, table: &str, id: u64) -> Result<(), String> {
+    let now = get_timestamp(&Duration::seconds(0));
+    let res = conn.execute(
+        &format!(
+            "UPDATE {} SET visits=visits+1, last_access=?1 WHERE id=?2",
+            table
+        ),
+        rusqlite::params![now, id],
+    );
+    if let Err(e) = res {
+        return Err(format!("Could not record visit\n{e}"));
+    }
     Ok(())
-} // update_entry
-
-// Stack routines
-
-fn get_timestamp(subtract: &Duration) -> i64 {
-    let utc: DateTime<Utc> = Utc::now();
-    (utc - *subtract).timestamp()
-} // get_timestamp
-
-/// Remove old entries from stack independent of sessionid
-fn tidyup_stack(conn: &Connection) -> Result<(), String> {
-    let best_after = get_timestamp(&Duration::days(STACKEXPIRE_DAYS));
+} // record_visit
 
+/// Query id, number of visits and time of last access of all visited rows.
+pub fn get_visits(conn: &Connection, table: &str) -> Result<Vec<(u64, u32, i64)>, String> {
     let stmt = conn.prepare(&format!(
-        "DELETE FROM {} WHERE timestamp < ?1",
-        STACKTABLENAME
+        "SELECT id, visits, last_access FROM {} WHERE last_access IS NOT NULL",
+        table
     ));
     if let Err(e) = stmt {
-        return Err(format!(
-            "Could not prepare tidyup stack delete statement\n{e}"
-        ));
+        return Err(format!("Could not prepare visits query statement\n{e}"));
     }
-    let mut stmt = stmt.unwrap();
 
-    let res = stmt.execute([best_after]);
-    if let Err(e) = res {
-        return Err(format!("Could not tidyup stack\n{e}"));
+    let mut stmt = stmt.unwrap();
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<usize, u64>(0)?,
+            row.get::<usize, u32>(1)?,
+            row.get::<usize, i64>(2)?,
+        ))
+    });
+    if let Err(e) = rows {
+        return Err(format!("Could not query visits\n{e}"));
     }
+    Ok(rows.unwrap().flatten().collect())
+} // get_visits
 
-    Ok(())
+/// Query all additional aliases as pairs of the id of the row they
+/// belong to and the alias. Resulting Vec is sorted by alias.
+pub fn get_aliases(conn: &Connection) -> Result<Vec<(u64, String)>, String> {
+    let stmt = conn.prepare(&format!(
+        "SELECT entry_id, alias FROM {} ORDER BY alias",
+        ALIASTABLENAME
+    ));
+    if let Err(e) = stmt {
+        return Err(format!("Could not prepare alias query statement\n{e}"));
+    }
+
+    let mut stmt = stmt.unwrap();
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<usize, u64>(0)?, row.get::<usize, String>(1)?))
+    });
+    if let Err(e) = rows {
+        return Err(format!("Could not query aliases\n{e}"));
+    }
+    Ok(rows.unwrap().flatten().collect())
+} // get_aliases
+
+/// Adds alias as additional alias to row corresponding to idx.
+pub fn add_alias(conn: &Connection, table: &str, idx: u32, alias: &str) -> Result<(), String> {
+    let _ = tidyup_main(conn, table);
+    let row = find_entry(conn, table, &Idx(idx), &AliasMatching::default())?;
+    if alias.is_empty() {
+        return Err("Alias must not be empty".to_string());
+    }
+    if contains_alias(conn, table, alias)? {
+        return Err("Alias already contained in table".to_string());
+    }
+
+    let res = conn.execute(
+        &format!(
+            "INSERT INTO {} (entry_id, alias) values (?1, ?2)",
+            ALIASTABLENAME
+        ),
+        rusqlite::params![row.id.unwrap(), alias],
+    );
+    if let Err(e) = res {
+        return Err(format!("Could not add alias\n{e}"));
+    }
+    Ok(())
+} // add_alias
+
+/// Removes additional alias from row corresponding to idx.
+pub fn rm_alias(conn: &Connection, table: &str, idx: u32, alias: &str) -> Result<(), String> {
+    let row = find_entry(conn, table, &Idx(idx), &AliasMatching::default())?;
+
+    let res = conn.execute(
+        &format!(
+            "DELETE FROM {} WHERE entry_id=?1 AND alias=?2",
+            ALIASTABLENAME
+        ),
+        rusqlite::params![row.id.unwrap(), alias],
+    );
+    match res {
+        Ok(0) => Err(ALIAS_NOT_FOUND.to_string()),
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Could not remove alias\n{e}")),
+    }
+} // rm_alias
+
+/// Search for a particular directory name
+pub fn search_dir(conn: &Connection, table: &str, directory: &Utf8Path) -> Result<StdRow, String> {
+    query_entry(conn, table, "directory", directory.as_str())
+} // search_dir
+
+/// Sets new idx or alias for row corresponding to idx
+pub fn update_entry(
+    conn: &Connection,
+    table: &str,
+    idx: u32,
+    entry: &IdxAlias,
+) -> Result<(), String> {
+    immediate_transaction(conn, |conn| {
+        let _ = tidyup_main(conn, table);
+        let row = find_entry(conn, table, &Idx(idx), &AliasMatching::default())?;
+
+        // Check if there is nothing to do and prevent duplicating values
+        match entry {
+            Idx(i) => {
+                if i == &row.idx {
+                    return Ok(());
+                }
+                if contains_idx(conn, table, *i)? {
+                    return Err("Idx already contained in table".to_string());
+                }
+            }
+            Alias(s) => {
+                if s == &row.alias {
+                    return Ok(());
+                }
+                if contains_alias(conn, table, s)? {
+                    return Err("Alias already contained in table".to_string());
+                }
+            }
+        }
+
+        let (col_name, new_value) = entry.to_colname_query();
+        let stmt = conn.prepare(&format!("UPDATE {} SET {}=?1 WHERE id=?2", table, col_name));
+        if let Err(e) = stmt {
+            return Err(format!("Could not prepare update statement\n{e}"));
+        }
+
+        let mut stmt = stmt.unwrap();
+        let res = stmt.execute(rusqlite::params![new_value, row.id]);
+        if let Err(e) = res {
+            return Err(format!("Could not update row\n{e}"));
+        }
+
+        Ok(())
+    })
+} // update_entry
+
+/// Sets new directory for row corresponding to entry (idx or alias).
+/// Fails if directory equals the current one or is registered with another entry.
+pub fn update_directory(
+    conn: &Connection,
+    table: &str,
+    entry: &IdxAlias,
+    directory: &Utf8Path,
+    matching: &AliasMatching,
+) -> Result<(), String> {
+    immediate_transaction(conn, |conn| {
+        let _ = tidyup_main(conn, table);
+        let row = find_entry(conn, table, entry, matching)?;
+        if row.directory == directory {
+            return Err(format!("Entry {} already has path {directory}", row.idx));
+        }
+        if let Ok(other) = search_dir(conn, table, directory) {
+            let mut msg = format!("Directory already registered with index {}", other.idx);
+            if !other.alias.is_empty() {
+                msg += &format!(" (alias {})", other.alias);
+            }
+            return Err(msg);
+        }
+
+        let res = conn.execute(
+            &format!("UPDATE {} SET directory=?1 WHERE id=?2", table),
+            rusqlite::params![directory.as_str(), row.id],
+        );
+        if let Err(e) = res {
+            return Err(format!("Could not update path\n{e}"));
+        }
+
+        Ok(())
+    })
+} // update_directory
+
+/// Sets new directories for rows given as (id, directory) within one transaction
+pub fn set_directories(
+    conn: &Connection,
+    table: &str,
+    changes: &[(u64, Utf8PathBuf)],
+) -> Result<(), String> {
+    let tx = conn.unchecked_transaction();
+    if let Err(e) = tx {
+        return Err(format!("Could not start transaction\n{e}"));
+    }
+    let tx = tx.unwrap();
+
+    {
+        let stmt = tx.prepare(&format!("UPDATE {} SET directory=?1 WHERE id=?2", table));
+        if let Err(e) = stmt {
+            return Err(format!("Could not prepare update statement\n{e}"));
+        }
+        let mut stmt = stmt.unwrap();
+        for (id, directory) in changes {
+            let res = stmt.execute(rusqlite::params![directory.as_str(), id]);
+            if let Err(e) = res {
+                return Err(format!("Could not update row\n{e}"));
+            }
+        }
+    }
+
+    if let Err(e) = tx.commit() {
+        return Err(format!("Could not commit changes\n{e}"));
+    }
+    Ok(())
+} // set_directories
+
+/// Sets idx of rows given by their id within one transaction.
+pub fn set_idxs(conn: &Connection, table: &str, changes: &[(u64, u32)]) -> Result<(), String> {
+    let tx = conn.unchecked_transaction();
+    if let Err(e) = tx {
+        return Err(format!("Could not start transaction\n{e}"));
+    }
+    let tx = tx.unwrap();
+
+    {
+        let stmt = tx.prepare(&format!("UPDATE {} SET idx=?1 WHERE id=?2", table));
+        if let Err(e) = stmt {
+            return Err(format!("Could not prepare update statement\n{e}"));
+        }
+        let mut stmt = stmt.unwrap();
+        for (id, idx) in changes {
+            let res = stmt.execute(rusqlite::params![idx, id]);
+            if let Err(e) = res {
+                return Err(format!("Could not update row\n{e}"));
+            }
+        }
+    }
+
+    if let Err(e) = tx.commit() {
+        return Err(format!("Could not commit changes\n{e}"));
+    }
+    Ok(())
+} // set_idxs
+
+/// Moves row corresponding to idx to idx 1 within one transaction.
+/// Rows with lower idx are shifted up by one.
+pub fn promote_entry(conn: &Connection, table: &str, idx: u32) -> Result<(), String> {
+    immediate_transaction(conn, |conn| {
+        let _ = tidyup_main(conn, table);
+        let row = find_entry(conn, table, &Idx(idx), &AliasMatching::default())?;
+        let offset = get_max_idx(conn, table)? + 1;
+
+        // Temporary offset prevents intermediate duplicates of idx values
+        let statements = [
+            (
+                format!("UPDATE {} SET idx=idx+?1 WHERE idx<?2", table),
+                rusqlite::params![offset, idx],
+            ),
+            (
+                format!("UPDATE {} SET idx=idx-?1+1 WHERE idx>=?1", table),
+                rusqlite::params![offset],
+            ),
+            (
+                format!("UPDATE {} SET idx=1 WHERE id=?1", table),
+                rusqlite::params![row.id],
+            ),
+        ];
+        for (sql, params) in statements {
+            if let Err(e) = conn.execute(&sql, params) {
+                return Err(format!("Could not renumber rows\n{e}"));
+            }
+        }
+
+        Ok(())
+    })
+} // promote_entry
+
+/// Moves row corresponding to idx to the highest idx within one
+/// transaction. Rows with higher idx are shifted down by one.
+pub fn demote_entry(conn: &Connection, table: &str, idx: u32) -> Result<(), String> {
+    immediate_transaction(conn, |conn| {
+        let _ = tidyup_main(conn, table);
+        let row = find_entry(conn, table, &Idx(idx), &AliasMatching::default())?;
+        let max_idx = get_max_idx(conn, table)?;
+        let offset = max_idx + 1;
+
+        // Temporary offset prevents intermediate duplicates of idx values
+        let statements = [
+            (
+                format!("UPDATE {} SET idx=idx+?1 WHERE idx>?2", table),
+                rusqlite::params![offset, idx],
+            ),
+            (
+                format!("UPDATE {} SET idx=?1 WHERE id=?2", table),
+                rusqlite::params![max_idx, row.id],
+            ),
+            (
+                format!("UPDATE {} SET idx=idx-?1-1 WHERE idx>?1", table),
+                rusqlite::params![offset],
+            ),
+        ];
+        for (sql, params) in statements {
+            if let Err(e) = conn.execute(&sql, params) {
+                return Err(format!("Could not renumber rows\n{e}"));
+            }
+        }
+
+        Ok(())
+    })
+} // demote_entry
+
+/// Moves row corresponding to entry to new_idx within one transaction.
+/// If new_idx is taken, the rows between both idx values are shifted by
+/// one towards the old idx of the row.
+pub fn move_entry(
+    conn: &Connection,
+    table: &str,
+    entry: &IdxAlias,
+    new_idx: u32,
+    matching: &AliasMatching,
+) -> Result<(), String> {
+    immediate_transaction(conn, |conn| {
+        let _ = tidyup_main(conn, table);
+        let row = find_entry(conn, table, entry, matching)?;
+        if row.idx == new_idx {
+            return Ok(());
+        }
+        let taken = find_entry(conn, table, &Idx(new_idx), &AliasMatching::default()).is_ok();
+        let offset = get_max_idx(conn, table)?.max(new_idx) + 1;
+
+        if taken {
+            // Temporary offset prevents intermediate duplicates of idx values
+            let (range, shift) = if new_idx < row.idx {
+                ("idx>=?2 AND idx<?3", 1)
+            } else {
+                ("idx>?3 AND idx<=?2", -1)
+            };
+            let statements = [
+                (
+                    format!("UPDATE {} SET idx=idx+?1 WHERE {}", table, range),
+                    rusqlite::params![offset, new_idx, row.idx],
+                ),
+                (
+                    format!("UPDATE {} SET idx=idx-?1+?2 WHERE idx>=?1", table),
+                    rusqlite::params![offset, shift],
+                ),
+            ];
+            for (sql, params) in statements {
+                if let Err(e) = conn.execute(&sql, params) {
+                    return Err(format!("Could not renumber rows\n{e}"));
+                }
+            }
+        }
+        if let Err(e) = conn.execute(
+            &format!("UPDATE {} SET idx=?1 WHERE id=?2", table),
+            rusqlite::params![new_idx, row.id],
+        ) {
+            return Err(format!("Could not update row\n{e}"));
+        }
+
+        Ok(())
+    })
+} // move_entry
+
+/// Renumbers all rows to contiguous idx values starting at 1 within one
+/// transaction, preserving their order. Returns the number of changed rows.
+pub fn compact_entries(conn: &Connection, table: &str) -> Result<usize, String> {
+    immediate_transaction(conn, |conn| {
+        let _ = tidyup_main(conn, table);
+        let mut entries = get_std_rows(conn, table)?;
+        entries.sort_by_key(|e| (e.idx, e.id));
+        let offset = get_max_idx(conn, table)? + 1;
+
+        // Temporary offset prevents intermediate duplicates of idx values
+        if let Err(e) = conn.execute(
+            &format!("UPDATE {} SET idx=idx+?1", table),
+            rusqlite::params![offset],
+        ) {
+            return Err(format!("Could not renumber rows\n{e}"));
+        }
+        let mut changed = 0;
+        {
+            let stmt = conn.prepare(&format!("UPDATE {} SET idx=?1 WHERE id=?2", table));
+            if let Err(e) = stmt {
+                return Err(format!("Could not prepare update statement\n{e}"));
+            }
+            let mut stmt = stmt.unwrap();
+            for (e, new_idx) in entries.iter().zip(1u32..) {
+                if let Err(e) = stmt.execute(rusqlite::params![new_idx, e.id]) {
+                    return Err(format!("Could not update row\n{e}"));
+                }
+                if e.idx != new_idx {
+                    changed += 1;
+                }
+            }
+        }
+
+        Ok(changed)
+    })
+} // compact_entries
+
+/// Exchanges the aliases of the rows corresponding to a and b within
+/// one transaction.
+pub fn swap_aliases(
+    conn: &Connection,
+    table: &str,
+    a: &IdxAlias,
+    b: &IdxAlias,
+    matching: &AliasMatching,
+) -> Result<(), String> {
+    immediate_transaction(conn, |conn| {
+        let row_a = find_entry(conn, table, a, matching)?;
+        let row_b = find_entry(conn, table, b, matching)?;
+
+        // Temporary empty alias prevents intermediate duplicates
+        let sql = format!("UPDATE {} SET alias=?1 WHERE id=?2", table);
+        let statements = [
+            rusqlite::params!["", row_a.id],
+            rusqlite::params![row_a.alias, row_b.id],
+            rusqlite::params![row_b.alias, row_a.id],
+        ];
+        for params in statements {
+            if let Err(e) = conn.execute(&sql, params) {
+                return Err(format!("Could not swap aliases\n{e}"));
+            }
+        }
+
+        Ok(())
+    })
+} // swap_aliases
+
+// Stack routines
+
+fn get_timestamp(subtract: &Duration) -> i64 {
+    let utc: DateTime<Utc> = Utc::now();
+    (utc - *subtract).timestamp()
+} // get_timestamp
+
+/// Remove expired entries (and their additional aliases) from tables like
+/// 'main'. Called by write paths only, reads skip expired rows instead.
+fn tidyup_main(conn: &Connection, table: &str) -> Result<(), String> {
+    let now = get_timestamp(&Duration::seconds(0));
+
+    let res = conn.execute(
+        &format!(
+            "DELETE FROM {} WHERE entry_id IN (SELECT id FROM {} WHERE expires_at <= ?1)",
+            ALIASTABLENAME, table
+        ),
+        [now],
+    );
+    if let Err(e) = res {
+        return Err(format!("Could not tidyup aliases of expired entries\n{e}"));
+    }
+    let res = conn.execute(
+        &format!("DELETE FROM {} WHERE expires_at <= ?1", table),
+        [now],
+    );
+    if let Err(e) = res {
+        return Err(format!("Could not tidyup expired entries\n{e}"));
+    }
+
+    Ok(())
+} // tidyup_main
+
+/// Days after which stack entries expire, parsed from the value of
+/// QCD_RS_STACK_EXPIRE_DAYS. Defaults to STACKEXPIRE_DAYS if unset or
+/// unparseable. None (value <= 0) disables expiry.
+pub fn stack_expire_days(val: Option<&str>) -> Option<i64> {
+    let days = match val {
+        Some(val) => val.trim().parse::<i64>().unwrap_or(STACKEXPIRE_DAYS),
+        None => STACKEXPIRE_DAYS,
+    };
+    (days > 0).then_some(days)
+} // stack_expire_days
+
+/// Maximum number of entries on the stack of a session, parsed from the
+/// value of QCD_RS_STACK_MAX. None (unset or unparseable) means no limit.
+pub fn stack_max_depth(val: Option<&str>) -> Option<u32> {
+    val?.trim().parse::<u32>().ok()
+} // stack_max_depth
+
+/// Remove entries older than expire_days from stack independent of
+/// sessionid
+fn tidyup_stack(conn: &Connection, expire_days: Option<i64>) -> Result<(), String> {
+    let Some(days) = expire_days else {
+        return Ok(());
+    };
+    let best_after = get_timestamp(&Duration::days(days));
+
+    let stmt = conn.prepare(&format!(
+        "DELETE FROM {} WHERE timestamp < ?1",
+        STACKTABLENAME
+    ));
+    if let Err(e) = stmt {
+        return Err(format!(
+            "Could not prepare tidyup stack delete statement\n{e}"
+        ));
+    }
+    let mut stmt = stmt.unwrap();
+
+    let res = stmt.execute([best_after]);
+    if let Err(e) = res {
+        return Err(format!("Could not tidyup stack\n{e}"));
+    }
+
+    Ok(())
 } // tidyup_stack
 
 /// Query all entries on the stack. Resulting Vec is sorted by id.
-pub fn get_stack_rows(conn: &Connection, sessionid: &str) -> Result<Vec<StackRow>, String> {
-    let _ = tidyup_stack(conn);
+pub fn get_stack_rows(
+    conn: &Connection,
+    sessionid: &str,
+    expire_days: Option<i64>,
+) -> Result<Vec<StackRow>, String> {
+    let _ = tidyup_stack(conn, expire_days);
 
     let stmt = conn.prepare(&format!(
         "SELECT * FROM {} WHERE sessionid=?1 ORDER BY id DESC",
@@ -443,6 +1565,7 @@ pub fn get_stack_rows(conn: &Connection, sessionid: &str) -> Result<Vec<StackRow
             row.get::<usize, u64>(0)?,
             row.get::<usize, String>(1)?,
             row.get::<usize, String>(3)?,
+            row.get::<usize, Option<String>>(4)?,
         ))
     });
     if let Err(e) = rows {
@@ -456,29 +1579,62 @@ pub fn get_stack_rows(conn: &Connection, sessionid: &str) -> Result<Vec<StackRow
             id: Some(r.0),
             sessionid: r.1,
             directory: Utf8PathBuf::from(r.2),
+            label: r.3,
         };
         entries.push(entry);
     }
     Ok(entries)
 } // get_stack_rows
 
-/// Add one row to stack. Returns id of entry.
-pub fn add_stack_dir(conn: &Connection, entry: &StackRow) -> Result<i64, String> {
-    let _ = tidyup_stack(conn);
-
-    let timestamp = get_timestamp(&Duration::seconds(0));
-    let res = conn.execute(
-        &format!(
-            "INSERT INTO {} (sessionid, timestamp, directory) values (?1, ?2, ?3)",
-            STACKTABLENAME
-        ),
-        rusqlite::params![entry.sessionid, timestamp, entry.directory.as_str()],
-    );
-    if let Err(e) = res {
-        return Err(format!("Could not add row to table\n{e}"));
-    }
+/// Add one row to stack, dropping the oldest rows of the session beyond
+/// max_depth (None means no limit). Returns id of entry.
+pub fn add_stack_dir(
+    conn: &Connection,
+    entry: &StackRow,
+    expire_days: Option<i64>,
+    max_depth: Option<u32>,
+) -> Result<i64, String> {
+    immediate_transaction(conn, |conn| {
+        let _ = tidyup_stack(conn, expire_days);
+
+        if max_depth == Some(0) {
+            return Err("Pushing onto stack is disabled (maximum depth is 0)".to_string());
+        }
+        let timestamp = get_timestamp(&Duration::seconds(0));
+        let res = conn.execute(
+            &format!(
+                "INSERT INTO {} (sessionid, timestamp, directory, label) values (?1, ?2, ?3, ?4)",
+                STACKTABLENAME
+            ),
+            rusqlite::params![
+                entry.sessionid,
+                timestamp,
+                entry.directory.as_str(),
+                entry.label
+            ],
+        );
+        if let Err(e) = res {
+            return Err(format!("Could not add row to table\n{e}"));
+        }
+        let id = conn.last_insert_rowid();
+
+        // Evict oldest entries of this session exceeding the limit
+        if let Some(max) = max_depth {
+            let res = conn.execute(
+                &format!(
+                    "DELETE FROM {0} WHERE sessionid=?1 AND id NOT IN \
+                 (SELECT id FROM {0} WHERE sessionid=?1 ORDER BY id DESC LIMIT ?2)",
+                    STACKTABLENAME
+                ),
+                rusqlite::params![entry.sessionid, max],
+            );
+            if let Err(e) = res {
+                return Err(format!("Could not evict old stack rows\n{e}"));
+            }
+        }
 
-    Ok(conn.last_insert_rowid())
+        Ok(id)
+    })
 } // add_stack_dir
 
 /// Removes row from stack
@@ -497,6 +1653,44 @@ fn rm_stack_dir(conn: &Connection, id: u64) -> Result<(), String> {
     Ok(())
 } // rm_stack_dir
 
+/// Removes all entries of sessionid from the stack. Returns the number
+/// of removed entries.
+pub fn stack_clear(conn: &Connection, sessionid: &str) -> Result<usize, String> {
+    let res = conn.execute(
+        &format!("DELETE FROM {} WHERE sessionid=?1", STACKTABLENAME),
+        [sessionid],
+    );
+    match res {
+        Ok(n) => Ok(n),
+        Err(e) => Err(format!("Could not clear stack\n{e}")),
+    }
+} // stack_clear
+
+/// Number of (not outdated) entries on the stack. Does not tidy up.
+pub fn stack_depth(
+    conn: &Connection,
+    sessionid: &str,
+    expire_days: Option<i64>,
+) -> Result<u32, String> {
+    let best_after = match expire_days {
+        Some(days) => get_timestamp(&Duration::days(days)),
+        None => i64::MIN,
+    };
+
+    let res = conn.query_row(
+        &format!(
+            "SELECT COUNT(*) FROM {} WHERE sessionid=?1 AND timestamp >= ?2",
+            STACKTABLENAME
+        ),
+        rusqlite::params![sessionid, best_after],
+        |row| row.get::<usize, u32>(0),
+    );
+    match res {
+        Ok(n) => Ok(n),
+        Err(e) => Err(format!("Could not count entries on stack\n{e}")),
+    }
+} // stack_depth
+
 /// Returns top element on stack
 pub fn stack_top(conn: &Connection, sessionid: &str) -> Result<StackRow, String> {
     let stmt = conn.prepare(&format!(
@@ -513,6 +1707,7 @@ pub fn stack_top(conn: &Connection, sessionid: &str) -> Result<StackRow, String>
             row.get::<usize, u64>(0)?,
             row.get::<usize, String>(1)?,
             row.get::<usize, String>(3)?,
+            row.get::<usize, Option<String>>(4)?,
         ))
     });
     if let Err(e) = rows {
@@ -525,24 +1720,227 @@ pub fn stack_top(conn: &Connection, sessionid: &str) -> Result<StackRow, String>
             id: Some(r.0),
             sessionid: r.1,
             directory: Utf8PathBuf::from(r.2),
+            label: r.3,
         };
         return Ok(entry);
     }
-    Err("Nothing on stack".to_string())
+    Err(STACK_EMPTY.to_string())
 } // stack_top
 
 /// Returns top of stack after removing that row from stack
-pub fn stack_pop(conn: &Connection, sessionid: &str) -> Result<StackRow, String> {
-    let _ = tidyup_stack(conn);
+pub fn stack_pop(
+    conn: &Connection,
+    sessionid: &str,
+    expire_days: Option<i64>,
+) -> Result<StackRow, String> {
+    immediate_transaction(conn, |conn| {
+        let _ = tidyup_stack(conn, expire_days);
 
-    let entry = stack_top(conn, sessionid)?;
+        let entry = stack_top(conn, sessionid)?;
 
-    match rm_stack_dir(conn, entry.id.unwrap()) {
-        Ok(()) => Ok(entry),
-        Err(e) => Err(e),
-    }
+        match rm_stack_dir(conn, entry.id.unwrap()) {
+            Ok(()) => Ok(entry),
+            Err(e) => Err(e),
+        }
+    })
 } // stack_pop
 
+/// Pops entries until the popped directory equals directory and returns
+/// that entry. If directory is not on the stack, the stack stays unchanged.
+pub fn stack_pop_to(
+    conn: &Connection,
+    sessionid: &str,
+    directory: &Utf8Path,
+    expire_days: Option<i64>,
+) -> Result<StackRow, String> {
+    immediate_transaction(conn, |conn| loop {
+        match stack_pop(conn, sessionid, expire_days) {
+            Ok(entry) if entry.directory == directory => return Ok(entry),
+            Ok(_) => {}
+            Err(e) if e == STACK_EMPTY => {
+                return Err(format!("{directory} is not on stack"));
+            }
+            Err(e) => return Err(e),
+        }
+    })
+} // stack_pop_to
+
+/// Exchanges the directories (and labels) of the entries at depths a and
+/// b (0 = top) within one transaction.
+pub fn stack_swap_depths(
+    conn: &Connection,
+    sessionid: &str,
+    a: usize,
+    b: usize,
+    expire_days: Option<i64>,
+) -> Result<(), String> {
+    immediate_transaction(conn, |conn| {
+        let rows = get_stack_rows(conn, sessionid, expire_days)?;
+        let (row_a, row_b) = match (rows.get(a), rows.get(b)) {
+            (Some(ra), Some(rb)) => (ra, rb),
+            _ => {
+                return Err(format!(
+                    "Depth {} out of range, stack holds {}",
+                    cmp::max(a, b),
+                    rows.len()
+                ))
+            }
+        };
+
+        let sql = format!(
+            "UPDATE {} SET directory=?1, label=?2 WHERE id=?3",
+            STACKTABLENAME
+        );
+        for (row, id) in [(row_b, row_a.id), (row_a, row_b.id)] {
+            let params = rusqlite::params![row.directory.as_str(), row.label, id];
+            if let Err(e) = conn.execute(&sql, params) {
+                return Err(format!("Could not swap stack entries\n{e}"));
+            }
+        }
+
+        Ok(())
+    })
+} // stack_swap_depths
+
+/// Moves the bottom entry of the stack to the top and returns it.
+/// A stack with a single entry stays unchanged.
+pub fn stack_rotate(
+    conn: &Connection,
+    sessionid: &str,
+    expire_days: Option<i64>,
+) -> Result<StackRow, String> {
+    immediate_transaction(conn, |conn| {
+        let rows = get_stack_rows(conn, sessionid, expire_days)?;
+        if rows.len() < 2 {
+            return match rows.into_iter().next() {
+                Some(r) => Ok(r),
+                None => Err(STACK_EMPTY.to_string()),
+            };
+        }
+        let bottom = rows.into_iter().last().unwrap();
+
+        rm_stack_dir(conn, bottom.id.unwrap())?;
+        let entry = StackRow {
+            id: None,
+            sessionid: sessionid.to_owned(),
+            directory: bottom.directory,
+            label: bottom.label,
+        };
+        let id = add_stack_dir(conn, &entry, expire_days, None)?;
+
+        Ok(StackRow {
+            id: Some(id as u64),
+            ..entry
+        })
+    })
+} // stack_rotate
+
+/// The stack of one session.
+///
+/// Bundles connection, sessionid and the expiry in days, outdated
+/// entries are removed by the methods where necessary. Pushing drops the
+/// oldest entries beyond the maximum depth (see with_max_depth).
+///
+/// ```ignore
+/// let conn = open_db(&PathBuf::from("qcd.sqlite"))?;
+/// let stack = Stack::new(&conn, "194811104321123401118419", Some(21)).with_max_depth(Some(10));
+/// stack.push(Utf8Path::new("/tmp"), None)?;
+/// assert_eq!(stack.peek()?.directory, Utf8PathBuf::from("/tmp"));
+/// assert_eq!(stack.pop()?.directory, Utf8PathBuf::from("/tmp"));
+/// assert!(stack.rows()?.is_empty());
+/// ```
+pub struct Stack<'a> {
+    conn: &'a Connection,
+    sessionid: String,
+    expire_days: Option<i64>,
+    max_depth: Option<u32>,
+}
+
+impl<'a> Stack<'a> {
+    pub fn new(conn: &'a Connection, sessionid: &str, expire_days: Option<i64>) -> Self {
+        Stack {
+            conn,
+            sessionid: sessionid.to_string(),
+            expire_days,
+            max_depth: None,
+        }
+    }
+
+    /// Sets the maximum number of entries kept when pushing, None means no limit.
+    pub fn with_max_depth(mut self, max_depth: Option<u32>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Adds directory (with optional label) on top unless it equals the
+    /// top entry already, checked within the same transaction. Returns the
+    /// id of the new row.
+    pub fn push_unless_top(
+        &self,
+        directory: &Utf8Path,
+        label: Option<&str>,
+    ) -> Result<Option<i64>, String> {
+        immediate_transaction(self.conn, |conn| {
+            let _ = tidyup_stack(conn, self.expire_days);
+            match stack_top(conn, &self.sessionid) {
+                Ok(top) if top.directory == directory => Ok(None),
+                _ => Stack::new(conn, &self.sessionid, self.expire_days)
+                    .with_max_depth(self.max_depth)
+                    .push(directory, label)
+                    .map(Some),
+            }
+        })
+    }
+
+    /// Adds directory (with optional label) on top, returns the id of the
+    /// new row.
+    pub fn push(&self, directory: &Utf8Path, label: Option<&str>) -> Result<i64, String> {
+        let entry = StackRow {
+            id: None,
+            sessionid: self.sessionid.clone(),
+            directory: directory.to_path_buf(),
+            label: label.map(str::to_string),
+        };
+        add_stack_dir(self.conn, &entry, self.expire_days, self.max_depth)
+    }
+
+    /// Removes the top entry and returns it.
+    pub fn pop(&self) -> Result<StackRow, String> {
+        stack_pop(self.conn, &self.sessionid, self.expire_days)
+    }
+
+    /// Removes entries down to directory and returns its entry.
+    pub fn pop_to(&self, directory: &Utf8Path) -> Result<StackRow, String> {
+        stack_pop_to(self.conn, &self.sessionid, directory, self.expire_days)
+    }
+
+    /// Returns the top entry, expired entries are removed before.
+    pub fn peek(&self) -> Result<StackRow, String> {
+        let _ = tidyup_stack(self.conn, self.expire_days);
+        stack_top(self.conn, &self.sessionid)
+    }
+
+    /// All entries, top first.
+    pub fn rows(&self) -> Result<Vec<StackRow>, String> {
+        get_stack_rows(self.conn, &self.sessionid, self.expire_days)
+    }
+
+    /// Removes all entries, returns their number.
+    pub fn clear(&self) -> Result<usize, String> {
+        stack_clear(self.conn, &self.sessionid)
+    }
+
+    /// Exchanges the entries at depths a and b (0 = top).
+    pub fn swap(&self, a: usize, b: usize) -> Result<(), String> {
+        stack_swap_depths(self.conn, &self.sessionid, a, b, self.expire_days)
+    }
+
+    /// Moves the bottom entry to the top and returns it.
+    pub fn rotate(&self) -> Result<StackRow, String> {
+        stack_rotate(self.conn, &self.sessionid, self.expire_days)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -551,13 +1949,80 @@ mod tests {
 
     const TESTDBNAME: &str = "test_qcd_database.sqlite";
 
+    const EXPIRE: Option<i64> = Some(STACKEXPIRE_DAYS);
+
     fn just_open_db() -> Connection {
         let _ = std::fs::remove_file(TESTDBNAME);
         let conn = open_db(&PathBuf::from(TESTDBNAME));
-        let conn = conn.unwrap();
-        conn
+        conn.unwrap()
     }
 
+    #[test]
+    #[serial]
+    fn schema_migration() {
+        let backup = format!("{TESTDBNAME}.bak-0");
+        let _ = std::fs::remove_file(TESTDBNAME);
+        let _ = std::fs::remove_file(&backup);
+        {
+            let conn = Connection::open(TESTDBNAME).unwrap();
+            let res = conn.execute(
+                &format!(
+                    "CREATE TABLE {} (id integer primary key, idx integer,
+                     directory text not null, alias text)",
+                    MAINTABLENAME
+                ),
+                (),
+            );
+            assert!(res.is_ok());
+            let res = conn.execute(
+                &format!(
+                    "CREATE TABLE {} (id integer primary key, sessionid text not null,
+                     timestamp integer not null, directory text not null)",
+                    STACKTABLENAME
+                ),
+                (),
+            );
+            assert!(res.is_ok());
+            assert_eq!(schema_version(&conn), Ok(0));
+        }
+
+        let conn = open_db(&PathBuf::from(TESTDBNAME)).unwrap();
+        assert_eq!(schema_version(&conn), Ok(SCHEMA_VERSION));
+        assert_eq!(has_column(&conn, MAINTABLENAME, "expires_at"), Ok(true));
+        assert_eq!(has_column(&conn, MAINTABLENAME, "visits"), Ok(true));
+        assert_eq!(has_column(&conn, STACKTABLENAME, "label"), Ok(true));
+        drop(conn);
+
+        // Backup holds the old schema and is only written once
+        {
+            let old = Connection::open(&backup).unwrap();
+            assert_eq!(schema_version(&old), Ok(0));
+            assert_eq!(has_column(&old, MAINTABLENAME, "expires_at"), Ok(false));
+        }
+        std::fs::remove_file(&backup).unwrap();
+        let conn = open_db(&PathBuf::from(TESTDBNAME)).unwrap();
+        assert_eq!(schema_version(&conn), Ok(SCHEMA_VERSION));
+        assert!(!Path::new(&backup).exists());
+
+        let res = conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1);
+        assert!(res.is_ok());
+        drop(conn);
+        assert!(open_db(&PathBuf::from(TESTDBNAME)).is_err());
+        let conn = just_open_db();
+        assert_eq!(schema_version(&conn), Ok(SCHEMA_VERSION));
+        // No backup of fresh databases
+        assert!(!Path::new(&backup).exists());
+    } // schema_migration
+
+    #[test]
+    fn numeric_aliases() {
+        assert!(check_alias("42").is_err());
+        assert!(check_alias("007").is_err());
+        assert_eq!(check_alias("a42"), Ok(()));
+        assert_eq!(check_alias("-1"), Ok(()));
+        assert_eq!(check_alias(""), Ok(()));
+    } // numeric_aliases
+
     #[test]
     #[serial]
     fn max_idx() {
@@ -678,7 +2143,7 @@ mod tests {
             }
         );
 
-        let fnd = find_entry(&conn, MAINTABLENAME, &Idx(44)).unwrap();
+        let fnd = find_entry(&conn, MAINTABLENAME, &Idx(44), &AliasMatching::default()).unwrap();
         assert_eq!(
             fnd,
             StdRow {
@@ -688,7 +2153,29 @@ mod tests {
                 alias: "fst".to_string()
             }
         );
-        let fnd = find_entry(&conn, MAINTABLENAME, &Alias("scd".to_string())).unwrap();
+        let fnd = find_entry(
+            &conn,
+            MAINTABLENAME,
+            &Alias("scd".to_string()),
+            &AliasMatching::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            fnd,
+            StdRow {
+                id: Some(2),
+                idx: 24,
+                directory: Utf8PathBuf::from_str("temp2").unwrap(),
+                alias: "scd".to_string()
+            }
+        );
+        let fnd = find_entry(
+            &conn,
+            MAINTABLENAME,
+            &Alias("s".to_string()),
+            &AliasMatching::default(),
+        )
+        .unwrap();
         assert_eq!(
             fnd,
             StdRow {
@@ -698,24 +2185,139 @@ mod tests {
                 alias: "scd".to_string()
             }
         );
-        let fnd = find_entry(&conn, MAINTABLENAME, &Alias("s".to_string())).unwrap();
+
+        let fnd = find_entry(&conn, MAINTABLENAME, &Idx(144), &AliasMatching::default());
+        assert_eq!(fnd, Err("Entry not contained in table".to_string()));
+        let fnd = find_entry(
+            &conn,
+            MAINTABLENAME,
+            &Alias("scdfst".to_string()),
+            &AliasMatching::default(),
+        );
+        assert_eq!(fnd, Err("Alias not found in table".to_string()));
+        let fnd = find_entry(
+            &conn,
+            MAINTABLENAME,
+            &Alias("f".to_string()),
+            &AliasMatching::default(),
+        );
+        assert_eq!(
+            fnd,
+            Err("Ambiguous alias specification: five, fst".to_string())
+        );
+    } // add_rows_get_rows
+
+    #[test]
+    #[serial]
+    fn subsequence_match() {
+        let conn = just_open_db();
+
+        for (idx, alias) in [(1, "work"), (2, "walk"), (3, "pets"), (4, "people")] {
+            let entry = StdRow {
+                id: None,
+                idx,
+                directory: Utf8PathBuf::from(format!("/home/{alias}")),
+                alias: alias.to_string(),
+            };
+            let _ = add_std_dir(&conn, MAINTABLENAME, &entry);
+        }
+
+        assert!(is_subsequence("wrk", "work"));
+        assert!(is_subsequence("WRK", "work"));
+        assert!(!is_subsequence("wkr", "work"));
+
+        let fnd = query_alias_subsequence(&conn, MAINTABLENAME, "wrk", &AliasMatching::default())
+            .unwrap();
+        assert_eq!(fnd.idx, 1);
+        let fnd = query_alias_subsequence(&conn, MAINTABLENAME, "pts", &AliasMatching::default())
+            .unwrap();
+        assert_eq!(fnd.idx, 3);
+        let fnd = query_alias_subsequence(&conn, MAINTABLENAME, "wk", &AliasMatching::default());
+        assert_eq!(
+            fnd,
+            Err("Ambiguous alias specification: walk, work".to_string())
+        );
+        let fnd = query_alias_subsequence(&conn, MAINTABLENAME, "xyz", &AliasMatching::default());
+        assert_eq!(fnd, Err(ALIAS_NOT_FOUND.to_string()));
+
+        let wrk = Alias("wrk".to_string());
+        let fnd = find_entry(&conn, MAINTABLENAME, &wrk, &AliasMatching::default());
+        assert_eq!(fnd, Err(ALIAS_NOT_FOUND.to_string()));
+        let matching = AliasMatching {
+            subsequence: true,
+            ..Default::default()
+        };
+        let fnd = find_entry(&conn, MAINTABLENAME, &wrk, &matching).unwrap();
+        assert_eq!(fnd.idx, 1);
+    } // subsequence_match
+
+    #[test]
+    fn alias_groups() {
+        let names: Vec<String> = ["proj.sub", "Proj.other", "project", "proj"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            alias_group(&names, "proj", "."),
+            vec!["Proj.other", "proj.sub"]
+        );
+        assert_eq!(alias_group(&names, "proj.s", "."), Vec::<String>::new());
+        assert_eq!(alias_group(&names, "pro", "."), Vec::<String>::new());
+        assert_eq!(alias_group(&names, "proj", "::"), Vec::<String>::new());
+    } // alias_groups
+
+    #[test]
+    #[serial]
+    fn alias_group_lookup() {
+        let conn = just_open_db();
+
+        for (idx, alias) in [(1, "proj.a"), (2, "proj.b"), (3, "other")] {
+            let entry = StdRow {
+                id: None,
+                idx,
+                directory: Utf8PathBuf::from(format!("/home/{alias}")),
+                alias: alias.to_string(),
+            };
+            let _ = add_std_dir(&conn, MAINTABLENAME, &entry);
+        }
+        let proj = Alias("proj".to_string());
+        let fnd = find_entry(&conn, MAINTABLENAME, &proj, &AliasMatching::default());
+        assert_eq!(
+            fnd,
+            Err("Ambiguous alias specification: proj.a, proj.b".to_string())
+        );
+        let matching = AliasMatching {
+            separator: Some(".".to_string()),
+            ..Default::default()
+        };
+        let fnd = find_entry(&conn, MAINTABLENAME, &proj, &matching);
+        assert_eq!(fnd, Err("Alias group proj: proj.a, proj.b".to_string()));
+    } // alias_group_lookup
+
+    #[test]
+    fn candidates_capped() {
+        let candidates: Vec<String> = ["alpha", "beta", "gamma", "delta"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            format_candidates(&candidates, 10),
+            "alpha, beta, gamma, delta"
+        );
         assert_eq!(
-            fnd,
-            StdRow {
-                id: Some(2),
-                idx: 24,
-                directory: Utf8PathBuf::from_str("temp2").unwrap(),
-                alias: "scd".to_string()
-            }
+            format_candidates(&candidates, 4),
+            "alpha, beta, gamma, delta"
+        );
+        assert_eq!(
+            format_candidates(&candidates, 2),
+            "alpha, beta (and 2 more)"
         );
+        assert_eq!(format_candidates(&candidates, 0), "(and 4 more)");
 
-        let fnd = find_entry(&conn, MAINTABLENAME, &Idx(144));
-        assert_eq!(fnd, Err("Entry not contained in table".to_string()));
-        let fnd = find_entry(&conn, MAINTABLENAME, &Alias("scdfst".to_string()));
-        assert_eq!(fnd, Err("Alias not found in table".to_string()));
-        let fnd = find_entry(&conn, MAINTABLENAME, &Alias("f".to_string()));
-        assert_eq!(fnd, Err("Ambiguous alias specification".to_string()));
-    } // add_rows_get_rows
+        assert_eq!(max_candidates(Some("3")), 3);
+        assert_eq!(max_candidates(Some("many")), MAXCANDIDATES);
+        assert_eq!(max_candidates(None), MAXCANDIDATES);
+    } // candidates_capped
 
     #[test]
     #[serial]
@@ -754,8 +2356,74 @@ mod tests {
         assert_eq!(entries.len(), 2);
         assert_eq!(entries[0].idx, 2);
         assert_eq!(entries[1].alias, "scd".to_string());
+
+        assert_eq!(rm_std_dirs(&conn, MAINTABLENAME, &[1, 3]), Ok(()));
+        assert!(get_std_rows(&conn, MAINTABLENAME).unwrap().is_empty());
     } // remove_row
 
+    #[test]
+    #[serial]
+    fn concurrent_add() {
+        let conn = just_open_db();
+        let other = open_db(&PathBuf::from(TESTDBNAME)).unwrap();
+        conn.busy_timeout(std::time::Duration::ZERO).unwrap();
+        let entry = |alias: &str| StdRow {
+            id: None,
+            idx: 7,
+            directory: Utf8PathBuf::from(format!("/{alias}")),
+            alias: alias.to_string(),
+        };
+
+        // other holds the write lock between its check and its insert
+        let tx = Transaction::new_unchecked(&other, TransactionBehavior::Immediate).unwrap();
+        assert_eq!(contains_idx(&tx, MAINTABLENAME, 7), Ok(false));
+        assert!(add_std_dir(&conn, MAINTABLENAME, &entry("first")).is_err());
+        assert_eq!(add_std_dir(&tx, MAINTABLENAME, &entry("second")), Ok(7));
+        tx.commit().unwrap();
+
+        let res = add_std_dir(&conn, MAINTABLENAME, &entry("third"));
+        assert_eq!(res, Err("Idx already exists!".to_string()));
+        let entries = get_std_rows(&conn, MAINTABLENAME).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].alias, "second");
+    } // concurrent_add
+
+    #[test]
+    #[serial]
+    fn next_idx_within_transaction() {
+        let conn = just_open_db();
+        let other = open_db(&PathBuf::from(TESTDBNAME)).unwrap();
+        conn.busy_timeout(std::time::Duration::ZERO).unwrap();
+        let entry = |dir: &str| StdRow {
+            id: None,
+            idx: 0,
+            directory: Utf8PathBuf::from(dir),
+            alias: "".to_string(),
+        };
+
+        // Maximum idx is determined while holding the write lock
+        let tx = Transaction::new_unchecked(&other, TransactionBehavior::Immediate).unwrap();
+        assert!(add_std_dir_next_idx(&conn, MAINTABLENAME, &entry("/a")).is_err());
+        assert_eq!(
+            add_std_dir_next_idx(&tx, MAINTABLENAME, &entry("/b")),
+            Ok(1)
+        );
+        tx.commit().unwrap();
+        assert_eq!(
+            add_std_dir_next_idx(&conn, MAINTABLENAME, &entry("/a")),
+            Ok(2)
+        );
+
+        let dirs = ["/c", "/a", "/d"].map(Utf8PathBuf::from);
+        assert_eq!(add_std_dirs_next_idx(&conn, MAINTABLENAME, &dirs), Ok(2));
+        let entries = get_std_rows(&conn, MAINTABLENAME).unwrap();
+        let rows: Vec<(u32, &str)> = entries
+            .iter()
+            .map(|e| (e.idx, e.directory.as_str()))
+            .collect();
+        assert_eq!(rows, vec![(1, "/b"), (2, "/a"), (3, "/c"), (4, "/d")]);
+    } // next_idx_within_transaction
+
     // Test stack functions
 
     #[test]
@@ -768,9 +2436,10 @@ mod tests {
             id: None,
             sessionid: sessionid.to_string(),
             directory: Utf8PathBuf::from("/home/east"),
+            label: None,
         };
-        let _ = add_stack_dir(&conn, &entry);
-        let rows = get_stack_rows(&conn, &sessionid).unwrap();
+        let _ = add_stack_dir(&conn, &entry, EXPIRE, None);
+        let rows = get_stack_rows(&conn, sessionid, EXPIRE).unwrap();
         assert_eq!(rows.len(), 1);
         assert_eq!(rows[0].directory, Utf8PathBuf::from("/home/east"));
 
@@ -778,9 +2447,10 @@ mod tests {
             id: None,
             sessionid: sessionid.to_string(),
             directory: Utf8PathBuf::from("/home/south"),
+            label: None,
         };
-        let _ = add_stack_dir(&conn, &entry);
-        let rows = get_stack_rows(&conn, &sessionid).unwrap();
+        let _ = add_stack_dir(&conn, &entry, EXPIRE, None);
+        let rows = get_stack_rows(&conn, sessionid, EXPIRE).unwrap();
         assert_eq!(rows.len(), 2);
         assert_eq!(rows[0].directory, Utf8PathBuf::from("/home/south"));
         assert_eq!(rows[1].directory, Utf8PathBuf::from("/home/east"));
@@ -788,14 +2458,14 @@ mod tests {
         let top = stack_top(&conn, sessionid).unwrap();
         assert_eq!(top.id.unwrap(), 2);
         let _ = rm_stack_dir(&conn, top.id.unwrap());
-        let rows = get_stack_rows(&conn, &sessionid).unwrap();
+        let rows = get_stack_rows(&conn, sessionid, EXPIRE).unwrap();
         assert_eq!(rows.len(), 1);
         assert_eq!(rows[0].directory, Utf8PathBuf::from("/home/east"));
 
         let top = stack_top(&conn, sessionid).unwrap();
         assert_eq!(top.id.unwrap(), 1);
         let _ = rm_stack_dir(&conn, top.id.unwrap());
-        let rows = get_stack_rows(&conn, &sessionid).unwrap();
+        let rows = get_stack_rows(&conn, sessionid, EXPIRE).unwrap();
         assert_eq!(rows.len(), 0);
     } // stack_add_remove
 
@@ -811,9 +2481,10 @@ mod tests {
             id: None,
             sessionid: sessionid.to_string(),
             directory: Utf8PathBuf::from("/etc/west"),
+            label: None,
         };
-        let _ = add_stack_dir(&conn, &entry);
-        let rows = get_stack_rows(&conn, &sessionid).unwrap();
+        let _ = add_stack_dir(&conn, &entry, EXPIRE, None);
+        let rows = get_stack_rows(&conn, sessionid, EXPIRE).unwrap();
         assert_eq!(rows.len(), 1);
         assert_eq!(rows[0].directory, Utf8PathBuf::from("/etc/west"));
 
@@ -821,9 +2492,10 @@ mod tests {
             id: None,
             sessionid: sessionid.to_string(),
             directory: Utf8PathBuf::from("/etc/north"),
+            label: None,
         };
-        let _ = add_stack_dir(&conn, &entry);
-        let rows = get_stack_rows(&conn, &sessionid).unwrap();
+        let _ = add_stack_dir(&conn, &entry, EXPIRE, None);
+        let rows = get_stack_rows(&conn, sessionid, EXPIRE).unwrap();
         assert_eq!(rows.len(), 2);
         assert_eq!(rows[0].directory, Utf8PathBuf::from("/etc/north"));
         assert_eq!(rows[1].directory, Utf8PathBuf::from("/etc/west"));
@@ -836,9 +2508,774 @@ mod tests {
             .unwrap();
         let res = stmt.execute([fake_timestamp]);
         assert!(res.is_ok());
-        let rows = get_stack_rows(&conn, &sessionid).unwrap();
+        let rows = get_stack_rows(&conn, sessionid, EXPIRE).unwrap();
         assert_eq!(rows.len(), 1);
         assert_eq!(rows[0].directory, Utf8PathBuf::from("/etc/north"));
         assert_eq!(rows[0].id, Some(2));
     } // stack_tidyup
+
+    #[test]
+    #[serial]
+    fn stack_tidyup_configured() {
+        let sessionid = "198411104321123401114820";
+        let conn = just_open_db();
+        let old_timestamp = get_timestamp(&Duration::days(2));
+        let set_old = |id: u64| {
+            conn.execute(
+                &format!("UPDATE {} SET timestamp=?1 WHERE id=?2", STACKTABLENAME),
+                rusqlite::params![old_timestamp, id],
+            )
+            .unwrap();
+        };
+
+        for dir in ["/etc/west", "/etc/north"] {
+            let entry = StackRow {
+                id: None,
+                sessionid: sessionid.to_string(),
+                directory: Utf8PathBuf::from(dir),
+                label: None,
+            };
+            let _ = add_stack_dir(&conn, &entry, EXPIRE, None);
+        }
+
+        set_old(1);
+        assert_eq!(stack_depth(&conn, sessionid, None).unwrap(), 2);
+        assert_eq!(get_stack_rows(&conn, sessionid, None).unwrap().len(), 2);
+
+        assert_eq!(stack_depth(&conn, sessionid, Some(1)).unwrap(), 1);
+        let rows = get_stack_rows(&conn, sessionid, Some(1)).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].directory, Utf8PathBuf::from("/etc/north"));
+
+        set_old(2);
+        assert_eq!(get_stack_rows(&conn, sessionid, None).unwrap().len(), 1);
+
+        // Expired top is not returned by peek
+        let top = Stack::new(&conn, sessionid, Some(1)).peek();
+        assert!(top.is_err());
+
+        assert_eq!(stack_expire_days(None), EXPIRE);
+        assert_eq!(stack_expire_days(Some("x")), EXPIRE);
+        assert_eq!(stack_expire_days(Some(" 7 ")), Some(7));
+        assert_eq!(stack_expire_days(Some("0")), None);
+        assert_eq!(stack_expire_days(Some("-3")), None);
+    } // stack_tidyup_configured
+
+    #[test]
+    #[serial]
+    fn stack_max_depth() {
+        let sessionid = "198411104321123401114821";
+        let other = "198411104321123401114822";
+        let conn = just_open_db();
+        let push = |sid: &str, dir: &str, max_depth: Option<u32>| {
+            let entry = StackRow {
+                id: None,
+                sessionid: sid.to_string(),
+                directory: Utf8PathBuf::from(dir),
+                label: None,
+            };
+            add_stack_dir(&conn, &entry, EXPIRE, max_depth)
+        };
+
+        assert!(push(other, "/other", None).is_ok());
+        for dir in ["/a", "/b", "/c"] {
+            assert!(push(sessionid, dir, Some(2)).is_ok());
+        }
+        let rows = get_stack_rows(&conn, sessionid, EXPIRE).unwrap();
+        let dirs: Vec<&str> = rows.iter().map(|r| r.directory.as_str()).collect();
+        assert_eq!(dirs, vec!["/c", "/b"]);
+        assert_eq!(get_stack_rows(&conn, other, EXPIRE).unwrap().len(), 1);
+
+        assert!(push(sessionid, "/d", Some(0)).is_err());
+        assert_eq!(get_stack_rows(&conn, sessionid, EXPIRE).unwrap().len(), 2);
+
+        assert_eq!(super::stack_max_depth(Some(" 5 ")), Some(5));
+        assert_eq!(super::stack_max_depth(Some("many")), None);
+        assert_eq!(super::stack_max_depth(None), None);
+    } // stack_max_depth
+
+    #[test]
+    #[serial]
+    fn stack_rotation() {
+        let sessionid = "194811104321123401118420";
+        let conn = just_open_db();
+
+        let res = stack_rotate(&conn, sessionid, EXPIRE);
+        assert_eq!(res, Err("Nothing on stack".to_string()));
+        assert_eq!(stack_depth(&conn, sessionid, EXPIRE), Ok(0));
+
+        for dir in ["/r/one", "/r/two", "/r/three"] {
+            let entry = StackRow {
+                id: None,
+                sessionid: sessionid.to_string(),
+                directory: Utf8PathBuf::from(dir),
+                label: None,
+            };
+            let _ = add_stack_dir(&conn, &entry, EXPIRE, None);
+        }
+        assert_eq!(stack_depth(&conn, sessionid, EXPIRE), Ok(3));
+
+        let top = stack_rotate(&conn, sessionid, EXPIRE).unwrap();
+        assert_eq!(top.directory, Utf8PathBuf::from("/r/one"));
+        let rows = get_stack_rows(&conn, sessionid, EXPIRE).unwrap();
+        let dirs: Vec<&str> = rows.iter().map(|r| r.directory.as_str()).collect();
+        assert_eq!(dirs, vec!["/r/one", "/r/three", "/r/two"]);
+        assert_eq!(stack_top(&conn, sessionid).unwrap(), top);
+
+        let top = stack_rotate(&conn, sessionid, EXPIRE).unwrap();
+        assert_eq!(top.directory, Utf8PathBuf::from("/r/two"));
+    } // stack_rotation
+
+    #[test]
+    #[serial]
+    fn stack_type() {
+        let conn = just_open_db();
+        let stack = Stack::new(&conn, "194811104321123401118421", EXPIRE);
+
+        assert!(stack.peek().is_err());
+        let _ = stack.push(Utf8Path::new("/s/one"), None);
+        let _ = stack.push(Utf8Path::new("/s/two"), None);
+        assert_eq!(stack.peek().unwrap().directory, Utf8PathBuf::from("/s/two"));
+        assert_eq!(
+            stack.rotate().unwrap().directory,
+            Utf8PathBuf::from("/s/one")
+        );
+        assert_eq!(stack.pop().unwrap().directory, Utf8PathBuf::from("/s/one"));
+        assert_eq!(stack.rows().unwrap().len(), 1);
+        assert_eq!(stack.clear(), Ok(1));
+        assert_eq!(stack.rows(), Ok(vec![]));
+    } // stack_type
+
+    #[test]
+    #[serial]
+    fn stack_cleared() {
+        let conn = just_open_db();
+        let stack = Stack::new(&conn, "194811104321123401118423", EXPIRE);
+        let other = Stack::new(&conn, "194811104321123401118424", EXPIRE);
+
+        for dir in ["/c/one", "/c/two", "/c/three"] {
+            let _ = stack.push(Utf8Path::new(dir), None);
+        }
+        let _ = other.push(Utf8Path::new("/c/other"), None);
+        assert_eq!(stack.clear(), Ok(3));
+        assert_eq!(stack.rows(), Ok(vec![]));
+        assert_eq!(other.rows().unwrap().len(), 1);
+        assert_eq!(stack.clear(), Ok(0));
+    } // stack_cleared
+
+    #[test]
+    #[serial]
+    fn stack_pop_until() {
+        let conn = just_open_db();
+        let stack = Stack::new(&conn, "194811104321123401118422", EXPIRE);
+        for dir in ["/p/one", "/p/two", "/p/three"] {
+            let _ = stack.push(Utf8Path::new(dir), None);
+        }
+
+        let res = stack.pop_to(Utf8Path::new("/p/four"));
+        assert_eq!(res, Err("/p/four is not on stack".to_string()));
+        assert_eq!(stack.rows().unwrap().len(), 3);
+
+        let res = stack.pop_to(Utf8Path::new("/p/two"));
+        assert_eq!(res.unwrap().directory, Utf8PathBuf::from("/p/two"));
+        let rows = stack.rows().unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].directory, Utf8PathBuf::from("/p/one"));
+    } // stack_pop_until
+
+    #[test]
+    #[serial]
+    fn stack_swapped_depths() {
+        let conn = just_open_db();
+        let stack = Stack::new(&conn, "194811104321123401118423", EXPIRE);
+        for dir in ["/w/one", "/w/two", "/w/three"] {
+            let _ = stack.push(Utf8Path::new(dir), None);
+        }
+
+        let res = stack.swap(0, 3);
+        assert_eq!(res, Err("Depth 3 out of range, stack holds 3".to_string()));
+        assert_eq!(stack.swap(0, 2), Ok(()));
+        let rows = stack.rows().unwrap();
+        let dirs: Vec<&str> = rows.iter().map(|r| r.directory.as_str()).collect();
+        assert_eq!(dirs, vec!["/w/one", "/w/two", "/w/three"]);
+
+        // Labels travel with their directories
+        let _ = stack.push(Utf8Path::new("/w/four"), Some("four"));
+        assert_eq!(stack.swap(0, 1), Ok(()));
+        let rows = stack.rows().unwrap();
+        assert_eq!(rows[0].label, None);
+        assert_eq!(rows[1].directory, Utf8PathBuf::from("/w/four"));
+        assert_eq!(rows[1].label, Some("four".to_string()));
+    } // stack_swapped_depths
+
+    #[test]
+    #[serial]
+    fn change_directories() {
+        let conn = just_open_db();
+
+        for (idx, dir) in [(1, "/old/a"), (2, "/old/b"), (3, "/other")] {
+            let entry = StdRow {
+                id: None,
+                idx,
+                directory: Utf8PathBuf::from(dir),
+                alias: "".to_string(),
+            };
+            let _ = add_std_dir(&conn, MAINTABLENAME, &entry);
+        }
+        let changes = vec![
+            (1, Utf8PathBuf::from("/new/a")),
+            (2, Utf8PathBuf::from("/new/b")),
+        ];
+        let res = set_directories(&conn, MAINTABLENAME, &changes);
+        assert_eq!(res, Ok(()));
+
+        let entries = get_std_rows(&conn, MAINTABLENAME).unwrap();
+        let dirs: Vec<&str> = entries.iter().map(|e| e.directory.as_str()).collect();
+        assert_eq!(dirs, vec!["/new/a", "/new/b", "/other"]);
+    } // change_directories
+
+    #[test]
+    #[serial]
+    fn schema() {
+        let conn = just_open_db();
+
+        let schema = get_schema(&conn).unwrap();
+        assert_eq!(schema.len(), 3);
+        assert!(schema[0].starts_with("CREATE TABLE _aliases"));
+        assert!(schema[1].starts_with("CREATE TABLE _stack"));
+        assert!(schema[2].starts_with("CREATE TABLE main"));
+    } // schema
+
+    #[test]
+    #[serial]
+    fn additional_aliases() {
+        let conn = just_open_db();
+
+        for (idx, dir, alias) in [(1, "/work", "work"), (2, "/docs", "docs")] {
+            let entry = StdRow {
+                id: None,
+                idx,
+                directory: Utf8PathBuf::from(dir),
+                alias: alias.to_string(),
+            };
+            let _ = add_std_dir(&conn, MAINTABLENAME, &entry);
+        }
+        assert_eq!(add_alias(&conn, MAINTABLENAME, 1, "job"), Ok(()));
+        assert_eq!(add_alias(&conn, MAINTABLENAME, 1, "wrk"), Ok(()));
+        assert!(add_alias(&conn, MAINTABLENAME, 2, "job").is_err());
+        assert!(add_alias(&conn, MAINTABLENAME, 2, "work").is_err());
+        assert!(add_alias(&conn, MAINTABLENAME, 3, "x").is_err());
+        assert_eq!(contains_alias(&conn, MAINTABLENAME, "job"), Ok(true));
+
+        let found = find_entry(
+            &conn,
+            MAINTABLENAME,
+            &Alias("job".to_string()),
+            &AliasMatching::default(),
+        )
+        .unwrap();
+        assert_eq!(found.idx, 1);
+        assert_eq!(found.alias, "work");
+        // Prefix of primary and additional alias of the same entry
+        let found = find_entry(
+            &conn,
+            MAINTABLENAME,
+            &Alias("w".to_string()),
+            &AliasMatching::default(),
+        )
+        .unwrap();
+        assert_eq!(found.idx, 1);
+        assert_eq!(add_alias(&conn, MAINTABLENAME, 2, "just"), Ok(()));
+        let res = find_entry(
+            &conn,
+            MAINTABLENAME,
+            &Alias("j".to_string()),
+            &AliasMatching::default(),
+        );
+        assert_eq!(
+            res,
+            Err("Ambiguous alias specification: job, just".to_string())
+        );
+
+        assert_eq!(rm_alias(&conn, MAINTABLENAME, 2, "just"), Ok(()));
+        assert_eq!(
+            rm_alias(&conn, MAINTABLENAME, 2, "just"),
+            Err(ALIAS_NOT_FOUND.to_string())
+        );
+        let _ = rm_std_dir(&conn, MAINTABLENAME, found.id.unwrap());
+        assert_eq!(get_aliases(&conn), Ok(vec![]));
+    } // additional_aliases
+
+    #[test]
+    #[serial]
+    fn expired_entries() {
+        let conn = just_open_db();
+
+        let past = get_timestamp(&Duration::seconds(1));
+        let future = get_timestamp(&Duration::days(-1));
+        for (idx, alias, expires_at) in [
+            (2, "new", Some(future)),
+            (3, "", None),
+            (1, "old", Some(past)),
+        ] {
+            let entry = StdRow {
+                id: None,
+                idx,
+                directory: Utf8PathBuf::from(format!("/dir{idx}")),
+                alias: alias.to_string(),
+            };
+            let res =
+                add_std_dir_with_aliases(&conn, MAINTABLENAME, &entry, &[], expires_at, false);
+            assert_eq!(res, Ok(idx));
+        }
+
+        let entries = get_std_rows(&conn, MAINTABLENAME).unwrap();
+        let idxs: Vec<u32> = entries.iter().map(|e| e.idx).collect();
+        assert_eq!(idxs, vec![2, 3]);
+        let res = find_entry(
+            &conn,
+            MAINTABLENAME,
+            &Alias("old".to_string()),
+            &AliasMatching::default(),
+        );
+        assert_eq!(res, Err(ALIAS_NOT_FOUND.to_string()));
+        let res = find_entry(&conn, MAINTABLENAME, &Idx(1), &AliasMatching::default());
+        assert_eq!(res, Err(ENTRY_NOT_FOUND.to_string()));
+        let res = find_entry(
+            &conn,
+            MAINTABLENAME,
+            &Alias("new".to_string()),
+            &AliasMatching::default(),
+        );
+        assert_eq!(res.unwrap().idx, 2);
+        let id = get_std_rows(&conn, MAINTABLENAME).unwrap()[0].id.unwrap();
+        assert_eq!(get_expiries(&conn, MAINTABLENAME), Ok(vec![(id, future)]));
+        assert_eq!(count_rows(&conn, MAINTABLENAME), Ok(2));
+
+        // Reads keep the expired row, only the cleanup removes it
+        let all = |conn: &Connection| {
+            conn.query_row(
+                &format!("SELECT COUNT(*) FROM {}", MAINTABLENAME),
+                [],
+                |row| row.get::<usize, u64>(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(all(&conn), 3);
+        assert_eq!(tidyup_main(&conn, MAINTABLENAME), Ok(()));
+        assert_eq!(all(&conn), 2);
+    } // expired_entries
+
+    #[test]
+    #[serial]
+    fn readonly_database() {
+        let conn = just_open_db();
+        let past = get_timestamp(&Duration::seconds(1));
+        let entry = StdRow {
+            id: None,
+            idx: 1,
+            directory: Utf8PathBuf::from("/old"),
+            alias: "old".to_string(),
+        };
+        let res = add_std_dir_with_aliases(&conn, MAINTABLENAME, &entry, &[], Some(past), false);
+        assert_eq!(res, Ok(1));
+        drop(conn);
+
+        let conn = open_db_readonly(Path::new(TESTDBNAME)).unwrap();
+        assert_eq!(get_std_rows(&conn, MAINTABLENAME), Ok(vec![]));
+        assert!(find_entry(&conn, MAINTABLENAME, &Idx(1), &AliasMatching::default()).is_err());
+        let res = conn.execute(&format!("DELETE FROM {}", MAINTABLENAME), ());
+        assert!(res.is_err());
+        drop(conn);
+
+        // Expired row is still there
+        let conn = Connection::open(TESTDBNAME).unwrap();
+        let n = conn
+            .query_row(
+                &format!("SELECT COUNT(*) FROM {}", MAINTABLENAME),
+                [],
+                |row| row.get::<usize, u64>(0),
+            )
+            .unwrap();
+        assert_eq!(n, 1);
+
+        // No tables are created
+        let res = conn.execute(&format!("DROP TABLE {}", MAINTABLENAME), ());
+        assert!(res.is_ok());
+        drop(conn);
+        assert!(open_db_readonly(Path::new(TESTDBNAME)).is_err());
+        let conn = Connection::open(TESTDBNAME).unwrap();
+        assert_eq!(has_table(&conn, MAINTABLENAME), Ok(false));
+    } // readonly_database
+
+    #[test]
+    #[serial]
+    fn add_with_aliases() {
+        let conn = just_open_db();
+        let entry = |idx: u32, alias: &str| StdRow {
+            id: None,
+            idx,
+            directory: Utf8PathBuf::from(format!("/{idx}")),
+            alias: alias.to_string(),
+        };
+        let extra = ["long".to_string(), "other".to_string()];
+
+        let res =
+            add_std_dir_with_aliases(&conn, MAINTABLENAME, &entry(1, "s"), &extra, None, false);
+        assert_eq!(res, Ok(1));
+        let res = find_entry(
+            &conn,
+            MAINTABLENAME,
+            &Alias("other".to_string()),
+            &AliasMatching::default(),
+        );
+        assert_eq!(res.unwrap().idx, 1);
+
+        // Collisions abort the whole add
+        let extra = ["new".to_string(), "long".to_string()];
+        let res =
+            add_std_dir_with_aliases(&conn, MAINTABLENAME, &entry(2, "t"), &extra, None, false);
+        assert!(res.is_err());
+        let extra = ["u".to_string()];
+        let res =
+            add_std_dir_with_aliases(&conn, MAINTABLENAME, &entry(3, "u"), &extra, None, false);
+        assert!(res.is_err());
+        assert_eq!(get_std_rows(&conn, MAINTABLENAME).unwrap().len(), 1);
+        assert_eq!(get_aliases(&conn).unwrap().len(), 2);
+    } // add_with_aliases
+
+    #[test]
+    #[serial]
+    fn promote() {
+        let conn = just_open_db();
+
+        for (idx, alias) in [(1, "a"), (2, "b"), (4, "c"), (6, "d")] {
+            let entry = StdRow {
+                id: None,
+                idx,
+                directory: Utf8PathBuf::from(format!("/{alias}")),
+                alias: alias.to_string(),
+            };
+            let _ = add_std_dir(&conn, MAINTABLENAME, &entry);
+        }
+        assert_eq!(promote_entry(&conn, MAINTABLENAME, 4), Ok(()));
+        assert!(promote_entry(&conn, MAINTABLENAME, 5).is_err());
+
+        let entries = get_std_rows(&conn, MAINTABLENAME).unwrap();
+        let rows: Vec<(u32, &str, &str)> = entries
+            .iter()
+            .map(|e| (e.idx, e.alias.as_str(), e.directory.as_str()))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (1, "c", "/c"),
+                (2, "a", "/a"),
+                (3, "b", "/b"),
+                (6, "d", "/d")
+            ]
+        );
+    } // promote
+
+    #[test]
+    #[serial]
+    fn demote() {
+        let conn = just_open_db();
+
+        for (idx, alias) in [(1, "a"), (3, "b"), (4, "c"), (6, "d")] {
+            let entry = StdRow {
+                id: None,
+                idx,
+                directory: Utf8PathBuf::from(format!("/{alias}")),
+                alias: alias.to_string(),
+            };
+            let _ = add_std_dir(&conn, MAINTABLENAME, &entry);
+        }
+        assert_eq!(demote_entry(&conn, MAINTABLENAME, 3), Ok(()));
+        assert!(demote_entry(&conn, MAINTABLENAME, 2).is_err());
+
+        let entries = get_std_rows(&conn, MAINTABLENAME).unwrap();
+        let rows: Vec<(u32, &str, &str)> = entries
+            .iter()
+            .map(|e| (e.idx, e.alias.as_str(), e.directory.as_str()))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (1, "a", "/a"),
+                (3, "c", "/c"),
+                (5, "d", "/d"),
+                (6, "b", "/b")
+            ]
+        );
+
+        assert_eq!(demote_entry(&conn, MAINTABLENAME, 6), Ok(()));
+        assert_eq!(get_std_rows(&conn, MAINTABLENAME).unwrap()[3].alias, "b");
+    } // demote
+
+    #[test]
+    #[serial]
+    fn moved_entry() {
+        let conn = just_open_db();
+
+        for (idx, alias) in [(1, "a"), (3, "b"), (4, "c"), (6, "d")] {
+            let entry = StdRow {
+                id: None,
+                idx,
+                directory: Utf8PathBuf::from(format!("/{alias}")),
+                alias: alias.to_string(),
+            };
+            let _ = add_std_dir(&conn, MAINTABLENAME, &entry);
+        }
+        let rows = |conn: &Connection| -> Vec<(u32, String)> {
+            get_std_rows(conn, MAINTABLENAME)
+                .unwrap()
+                .into_iter()
+                .map(|e| (e.idx, e.alias))
+                .collect()
+        };
+        let expected = |v: &[(u32, &str)]| -> Vec<(u32, String)> {
+            v.iter().map(|(i, a)| (*i, a.to_string())).collect()
+        };
+
+        // Free target
+        assert_eq!(
+            move_entry(&conn, MAINTABLENAME, &Idx(6), 9, &AliasMatching::default()),
+            Ok(())
+        );
+        assert_eq!(
+            rows(&conn),
+            expected(&[(1, "a"), (3, "b"), (4, "c"), (9, "d")])
+        );
+        // Taken target below
+        let entry = Alias("d".to_string());
+        assert_eq!(
+            move_entry(&conn, MAINTABLENAME, &entry, 3, &AliasMatching::default()),
+            Ok(())
+        );
+        assert_eq!(
+            rows(&conn),
+            expected(&[(1, "a"), (3, "d"), (4, "b"), (5, "c")])
+        );
+        // Taken target above
+        assert_eq!(
+            move_entry(&conn, MAINTABLENAME, &Idx(1), 4, &AliasMatching::default()),
+            Ok(())
+        );
+        assert_eq!(
+            rows(&conn),
+            expected(&[(2, "d"), (3, "b"), (4, "a"), (5, "c")])
+        );
+        assert!(move_entry(&conn, MAINTABLENAME, &Idx(1), 2, &AliasMatching::default()).is_err());
+    } // moved_entry
+
+    #[test]
+    #[serial]
+    fn compacted() {
+        let conn = just_open_db();
+
+        assert_eq!(compact_entries(&conn, MAINTABLENAME), Ok(0));
+        for (idx, alias) in [(52, "c"), (12, "a"), (24, "b")] {
+            let entry = StdRow {
+                id: None,
+                idx,
+                directory: Utf8PathBuf::from(format!("/{alias}")),
+                alias: alias.to_string(),
+            };
+            let _ = add_std_dir(&conn, MAINTABLENAME, &entry);
+        }
+        assert_eq!(compact_entries(&conn, MAINTABLENAME), Ok(3));
+        let entries = get_std_rows(&conn, MAINTABLENAME).unwrap();
+        let rows: Vec<(u32, &str, &str)> = entries
+            .iter()
+            .map(|e| (e.idx, e.alias.as_str(), e.directory.as_str()))
+            .collect();
+        assert_eq!(rows, vec![(1, "a", "/a"), (2, "b", "/b"), (3, "c", "/c")]);
+        assert_eq!(compact_entries(&conn, MAINTABLENAME), Ok(0));
+    } // compacted
+
+    #[test]
+    #[serial]
+    fn swapped_aliases() {
+        let conn = just_open_db();
+
+        for (idx, alias) in [(1, "left"), (2, "right"), (3, "")] {
+            let entry = StdRow {
+                id: None,
+                idx,
+                directory: Utf8PathBuf::from(format!("/{idx}")),
+                alias: alias.to_string(),
+            };
+            let _ = add_std_dir(&conn, MAINTABLENAME, &entry);
+        }
+        let res = swap_aliases(
+            &conn,
+            MAINTABLENAME,
+            &Alias("left".to_string()),
+            &Idx(2),
+            &AliasMatching::default(),
+        );
+        assert_eq!(res, Ok(()));
+        let res = swap_aliases(
+            &conn,
+            MAINTABLENAME,
+            &Idx(1),
+            &Idx(3),
+            &AliasMatching::default(),
+        );
+        assert_eq!(res, Ok(()));
+        assert!(swap_aliases(
+            &conn,
+            MAINTABLENAME,
+            &Idx(1),
+            &Idx(4),
+            &AliasMatching::default()
+        )
+        .is_err());
+
+        let entries = get_std_rows(&conn, MAINTABLENAME).unwrap();
+        let aliases: Vec<&str> = entries.iter().map(|e| e.alias.as_str()).collect();
+        assert_eq!(aliases, vec!["", "left", "right"]);
+    } // swapped_aliases
+
+    #[test]
+    #[serial]
+    fn updated_directory() {
+        let conn = just_open_db();
+
+        let entry = StdRow {
+            id: None,
+            idx: 4,
+            directory: Utf8PathBuf::from("/old"),
+            alias: "proj".to_string(),
+        };
+        let _ = add_std_dir(&conn, MAINTABLENAME, &entry);
+        let new_dir = Utf8Path::new("/new");
+        let res = update_directory(
+            &conn,
+            MAINTABLENAME,
+            &Alias("proj".to_string()),
+            new_dir,
+            &AliasMatching::default(),
+        );
+        assert_eq!(res, Ok(()));
+        assert!(update_directory(
+            &conn,
+            MAINTABLENAME,
+            &Idx(4),
+            new_dir,
+            &AliasMatching::default()
+        )
+        .is_err());
+        assert!(update_directory(
+            &conn,
+            MAINTABLENAME,
+            &Idx(5),
+            new_dir,
+            &AliasMatching::default()
+        )
+        .is_err());
+
+        let other = StdRow {
+            id: None,
+            idx: 6,
+            directory: Utf8PathBuf::from("/other"),
+            alias: "".to_string(),
+        };
+        let _ = add_std_dir(&conn, MAINTABLENAME, &other);
+        let res = update_directory(
+            &conn,
+            MAINTABLENAME,
+            &Idx(6),
+            new_dir,
+            &AliasMatching::default(),
+        );
+        assert_eq!(
+            res,
+            Err("Directory already registered with index 4 (alias proj)".to_string())
+        );
+
+        let row = find_entry(&conn, MAINTABLENAME, &Idx(4), &AliasMatching::default()).unwrap();
+        assert_eq!(row.directory, new_dir);
+        assert_eq!(row.alias, "proj");
+    } // updated_directory
+
+    #[test]
+    #[serial]
+    fn directories_containing() {
+        let conn = just_open_db();
+
+        for (idx, dir) in [(1, "/home/me/Proj_a"), (2, "/srv/proj%b"), (3, "/opt/tool")] {
+            let entry = StdRow {
+                id: None,
+                idx,
+                directory: Utf8PathBuf::from(dir),
+                alias: String::new(),
+            };
+            let _ = add_std_dir(&conn, MAINTABLENAME, &entry);
+        }
+        let idxs = |part: &str| -> Vec<u32> {
+            let rows = search_dirs_containing(&conn, MAINTABLENAME, part).unwrap();
+            rows.iter().map(|r| r.idx).collect()
+        };
+        assert_eq!(idxs("proj"), vec![1, 2]);
+        assert_eq!(idxs("j_"), vec![1]);
+        assert_eq!(idxs("j%"), vec![2]);
+        assert_eq!(idxs("tool"), vec![3]);
+        assert_eq!(idxs("nothing"), Vec::<u32>::new());
+    } // directories_containing
+
+    #[test]
+    #[serial]
+    fn visits() {
+        let conn = just_open_db();
+
+        for idx in [1, 2] {
+            let entry = StdRow {
+                id: None,
+                idx,
+                directory: Utf8PathBuf::from(format!("/{idx}")),
+                alias: String::new(),
+            };
+            let _ = add_std_dir(&conn, MAINTABLENAME, &entry);
+        }
+        assert_eq!(get_visits(&conn, MAINTABLENAME), Ok(vec![]));
+        assert_eq!(record_visit(&conn, MAINTABLENAME, 2), Ok(()));
+        assert_eq!(record_visit(&conn, MAINTABLENAME, 2), Ok(()));
+
+        let visits = get_visits(&conn, MAINTABLENAME).unwrap();
+        assert_eq!(visits.len(), 1);
+        assert_eq!((visits[0].0, visits[0].1), (2, 2));
+    } // visits
+
+    #[test]
+    #[serial]
+    fn null_directory() {
+        let conn = just_open_db();
+
+        let res = conn.execute(
+            "CREATE TABLE legacy (id integer primary key, idx integer, directory text, alias text,
+             expires_at integer)",
+            (),
+        );
+        assert!(res.is_ok());
+        let res = conn.execute(
+            "INSERT INTO legacy (idx, directory, alias) VALUES (1, NULL, 'a'), (2, '/b', NULL)",
+            (),
+        );
+        assert!(res.is_ok());
+
+        let entries = get_std_rows(&conn, "legacy").unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].directory, Utf8PathBuf::from(""));
+        assert_eq!(entries[1].alias, "");
+        let found = find_entry(
+            &conn,
+            "legacy",
+            &Alias("a".to_string()),
+            &AliasMatching::default(),
+        )
+        .unwrap();
+        assert_eq!(found.directory, Utf8PathBuf::from(""));
+    } // null_directory
 } // mod tests
@@ -4,17 +4,17 @@ mod db;
 use crate::db::IdxAlias::{Alias, Idx};
 use camino::Utf8PathBuf;
 use chrono::Utc;
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process;
 
 fn main() {
     let args = options::Arguments::try_parse();
     if let Err(e) = args {
-        // We need to use correct exit code if help is requested
+        // Help and version exit with 0, usage errors with 2
         e.print().expect("Error writing Error");
-        process::exit(1);
+        process::exit(e.exit_code());
     }
     let args = args.unwrap();
 
@@ -22,6 +22,24 @@ fn main() {
     const DBNAME: &str = ".qcd_rs.sqlite";
     const DBNAME_KEY: &str = "QCD_RS_DBNAME";
     const DBPATH_KEY: &str = "QCD_RS_DBPATH";
+    const HOME_KEY: &str = "QCD_RS_HOME";
+    const HIDEPATTERN_KEY: &str = "QCD_RS_HIDE_PATTERN";
+    const REQUIREDB_KEY: &str = "QCD_RS_REQUIRE_DB";
+    const LISTFILL_KEY: &str = "QCD_RS_LIST_FILL";
+    const TIMEFORMAT_KEY: &str = "QCD_RS_TIME_FORMAT";
+    const TIMEZONE_KEY: &str = "QCD_RS_TIMEZONE";
+    const STACKEXPIRE_KEY: &str = "QCD_RS_STACK_EXPIRE_DAYS";
+    const STACKMAX_KEY: &str = "QCD_RS_STACK_MAX";
+    const TOUCHONECHO_KEY: &str = "QCD_RS_TOUCH_ON_ECHO";
+    const ERRFMT_KEY: &str = "QCD_RS_ERROR_FORMAT";
+    const MAXCANDIDATES_KEY: &str = "QCD_RS_MAX_CANDIDATES";
+    const AUTOVACUUM_KEY: &str = "QCD_RS_AUTO_VACUUM";
+    const MATCH_KEY: &str = "QCD_RS_MATCH";
+    const ALIASSEP_KEY: &str = "QCD_RS_ALIAS_SEP";
+
+    if env::var(ERRFMT_KEY).is_ok_and(|v| v == "json") {
+        actions::use_json_errors();
+    }
 
     let sessionid = match env::var(SESSID_KEY) {
         Ok(val) => val,
@@ -29,6 +47,13 @@ fn main() {
     };
 
     let use_stack = sessionid.len() > 22;
+    let stack_expire = db::stack_expire_days(env::var(STACKEXPIRE_KEY).ok().as_deref());
+    let stack_max = db::stack_max_depth(env::var(STACKMAX_KEY).ok().as_deref());
+    let matching = db::AliasMatching {
+        max_candidates: db::max_candidates(env::var(MAXCANDIDATES_KEY).ok().as_deref()),
+        subsequence: matches!(env::var(MATCH_KEY), Ok(val) if val == "subsequence"),
+        separator: env::var(ALIASSEP_KEY).ok().filter(|s| !s.is_empty()),
+    };
 
     if args.methods.pid {
         let now = Utc::now();
@@ -37,7 +62,19 @@ fn main() {
         } else {
             println!("{}", now.format("%Y%m%d%H%M%S%f"));
         }
-        process::exit(1);
+        process::exit(actions::EXIT_OK);
+    }
+
+    // Never touches the database
+    if args.methods.self_test {
+        actions::self_test();
+    }
+
+    // Print completion script for the shell function qcd
+    if let Some(shell) = args.methods.completions {
+        let mut cmd = options::Arguments::command();
+        clap_complete::generate(shell, &mut cmd, "qcd", &mut std::io::stdout());
+        process::exit(actions::EXIT_OK);
     }
 
     let db_name = match env::var(DBNAME_KEY) {
@@ -46,10 +83,51 @@ fn main() {
     };
     let mut db_fullpath = match env::var(DBPATH_KEY) {
         Ok(val) => PathBuf::from(val),
-        Err(_) => simple_home_dir::home_dir().unwrap(),
+        Err(_) => {
+            let home = match env::var(HOME_KEY) {
+                Ok(val) => PathBuf::from(val),
+                Err(_) => simple_home_dir::home_dir().unwrap(),
+            };
+            default_db_dir(home, &db_name)
+        }
     };
     db_fullpath.push(db_name);
 
+    let auto_vacuum = env::var(AUTOVACUUM_KEY).is_ok_and(|v| !v.is_empty());
+
+    // Explicit creation of database
+    if args.methods.create_db {
+        actions::create_db(&db_fullpath, auto_vacuum);
+    }
+
+    // Replace database by another one
+    if let Some(file) = args.methods.replace_db {
+        actions::replace_db(&db_fullpath, file.as_std_path());
+    }
+
+    // Database at custom location is only created implicitly if not required
+    if !db_fullpath.exists() {
+        let require_db = env::var(REQUIREDB_KEY).is_ok_and(|v| !v.is_empty());
+        if require_db && env::var(DBPATH_KEY).is_ok() {
+            actions::exit_with_error(
+                actions::ErrorKind::NoDatabase,
+                &format!(
+                    "Database {} does not exist (see --create-db)",
+                    db_fullpath.display()
+                ),
+            );
+        }
+        eprintln!("Creating new database {}", db_fullpath.display());
+        // auto_vacuum can only be chosen on creation, not by later opens
+        if auto_vacuum {
+            if let Err(e) = db::open_db_auto_vacuum(&db_fullpath, true) {
+                actions::exit_with_error(actions::ErrorKind::General, &e);
+            }
+        }
+    } else if auto_vacuum {
+        actions::warn_auto_vacuum(&db_fullpath);
+    }
+
     // Actions
 
     let tablename = &db::MAINTABLENAME;
@@ -61,95 +139,455 @@ fn main() {
         } else {
             Some(get_cwd())
         };
-        actions::chdir(&db_fullpath, tablename, &entry, push_dir, &sessionid);
+        let subdir = args.subdir.as_deref();
+        let opts = actions::ChdirOptions {
+            allow_missing: args.allow_missing,
+            porcelain: args.porcelain,
+            stack_expire,
+            stack_max,
+            matching,
+        };
+        actions::chdir(
+            &db_fullpath,
+            tablename,
+            &entry,
+            subdir,
+            push_dir,
+            &sessionid,
+            &opts,
+        );
+    }
+
+    // Chdir to entry or directory
+    if let Some(arg) = args.methods.go {
+        let push_dir = if !use_stack || args.no_push {
+            None
+        } else {
+            Some(get_cwd())
+        };
+        let opts = actions::ChdirOptions {
+            allow_missing: args.allow_missing,
+            stack_expire,
+            stack_max,
+            matching,
+            ..Default::default()
+        };
+        actions::go(&db_fullpath, tablename, &arg, push_dir, &sessionid, &opts);
+    }
+
+    // Select entry interactively and chdir
+    if args.methods.menu {
+        let push_dir = if !use_stack || args.no_push {
+            None
+        } else {
+            Some(get_cwd())
+        };
+        let opts = actions::ChdirOptions {
+            allow_missing: args.allow_missing,
+            stack_expire,
+            stack_max,
+            matching,
+            ..Default::default()
+        };
+        actions::menu(&db_fullpath, tablename, push_dir, &sessionid, &opts);
     }
 
     // Print contents of (main) table
     if args.methods.list_paths {
-        actions::list_dirs(&db_fullpath, tablename);
+        let hide_pattern = if args.show_hidden {
+            None
+        } else {
+            env::var(HIDEPATTERN_KEY).ok()
+        };
+        let opts = actions::ListOptions {
+            git: args.git,
+            hide_pattern,
+            status: args.status,
+            fill: env::var(LISTFILL_KEY).ok(),
+            long: args.long,
+            absolute_only: args.absolute_only,
+            relative_only: args.relative_only,
+            jsonl: args.jsonl,
+            json: args.format.as_deref() == Some("json") || args.json_list,
+            time_format: env::var(TIMEFORMAT_KEY).ok(),
+            timezone: env::var(TIMEZONE_KEY).ok(),
+            existing_first: args.existing_first,
+            under: args.under,
+            matching,
+            tree: args.tree,
+            sort: args.sort,
+        };
+        actions::list_dirs(&db_fullpath, tablename, &opts);
     }
 
     // Add path to database
     if args.methods.add.is_some() || args.methods.add_current {
-        let path = args.methods.add.unwrap_or_else(get_cwd);
+        let mut path = args.methods.add.unwrap_or_else(get_cwd);
+        if let Some(levels) = args.parent {
+            path = match actions::parent_dir(&path, levels.unwrap_or(1)) {
+                Ok(p) => p,
+                Err(e) => actions::exit_with_error(actions::ErrorKind::Path, &e),
+            };
+        }
         let idx = args.idx;
-        let alias = args.alias;
-        actions::add_row(&db_fullpath, tablename, idx, path, alias);
+        let aliases = args.alias;
+        for a in &aliases {
+            actions::validate_alias(a, args.force);
+        }
+        let expire = args.expire.map(|ttl| match actions::parse_ttl(&ttl) {
+            Ok(d) => d,
+            Err(e) => actions::exit_with_error(actions::ErrorKind::Usage, &e),
+        });
+        if !args.allow_duplicate {
+            actions::check_registered(
+                &db_fullpath,
+                tablename,
+                &path,
+                args.lexical,
+                args.only_if_changed,
+                args.verbose,
+            );
+        }
+        actions::add_row(
+            &db_fullpath,
+            tablename,
+            idx,
+            path,
+            aliases,
+            args.lexical,
+            expire,
+        );
+    }
+
+    // Add current work dir with alias
+    if let Some(alias) = args.methods.bookmark {
+        actions::validate_alias(&alias, args.force);
+        if args.only_if_changed {
+            let cwd = get_cwd();
+            actions::check_registered(&db_fullpath, tablename, &cwd, false, true, args.verbose);
+        }
+        actions::bookmark(&db_fullpath, tablename, get_cwd(), alias, args.force);
+    }
+
+    // Print aliases as environment variables
+    if let Some(prefix) = args.methods.export_env {
+        actions::export_env(&db_fullpath, tablename, &prefix);
     }
 
     // Query a single directory
     if let Some(entry) = args.methods.echo {
-        actions::print_row(&db_fullpath, tablename, &entry);
+        if args.json {
+            actions::use_json_errors();
+        }
+        let opts = actions::EchoOptions {
+            json: args.json,
+            style: args.path_style,
+            allow_missing: args.allow_missing,
+            touch: env::var(TOUCHONECHO_KEY).is_ok_and(|v| !v.is_empty()),
+            matching,
+        };
+        actions::print_row(&db_fullpath, tablename, &entry, &opts);
+    }
+
+    // Query many directories
+    if let Some(entries) = args.methods.resolve_all {
+        actions::resolve_all(&db_fullpath, tablename, &entries, &matching);
     }
 
-    // Delete entry from database
-    if let Some(entry) = args.methods.remove {
-        actions::remove_row(&db_fullpath, tablename, &entry);
+    // Delete entries from database
+    if let Some(entries) = args.methods.remove {
+        actions::remove_row(&db_fullpath, tablename, &entries, args.dry_run, &matching);
     }
 
     // Change alias or idx
     if args.methods.new_alias.is_some() || args.methods.new_idx.is_some() {
         let idx: u32;
         let entry: db::IdxAlias;
-        if args.methods.new_idx.is_some() {
-            let v = args.methods.new_idx.unwrap();
+        if let Some(v) = args.methods.new_idx {
             idx = v[0];
             entry = Idx(v[1]);
         } else {
             let v = args.methods.new_alias.unwrap();
             idx = match v[0].parse::<u32>() {
                 Ok(n) => n,
-                Err(_) => {
-                    println!("ERROR: Not an idx value");
-                    process::exit(1);
-                }
+                Err(_) => actions::exit_with_error(actions::ErrorKind::Usage, "Not an idx value"),
             };
             entry = Alias(v[1].clone());
         }
-        actions::update_row(&db_fullpath, tablename, idx, &entry);
+        actions::update_row(&db_fullpath, tablename, idx, &entry, args.dry_run);
+    }
+
+    // Move entry to idx 1
+    if let Some(idx) = args.methods.promote {
+        actions::promote(&db_fullpath, tablename, idx);
+    }
+
+    // Move entry to highest idx
+    if let Some(idx) = args.methods.demote {
+        actions::demote(&db_fullpath, tablename, idx);
+    }
+
+    // Move entry to given idx
+    if let Some(v) = args.methods.move_to {
+        let idx = match v[1].parse::<u32>() {
+            Ok(n) => n,
+            Err(_) => actions::exit_with_error(actions::ErrorKind::Usage, "Not an idx value"),
+        };
+        actions::move_row(&db_fullpath, tablename, &v[0], idx, &matching);
+    }
+
+    // Renumber entries contiguously
+    if args.methods.compact {
+        actions::compact(&db_fullpath, tablename);
+    }
+
+    // Change alias of entry given by idx or alias
+    if let Some(v) = args.methods.rename {
+        actions::rename(&db_fullpath, tablename, &v[0], &v[1], &matching);
+    }
+
+    // Change path of entry given by idx or alias
+    if let Some(v) = args.methods.set_path {
+        let path = Utf8PathBuf::from(&v[1]);
+        actions::update_path(
+            &db_fullpath,
+            tablename,
+            &v[0],
+            path,
+            args.allow_missing,
+            &matching,
+        );
+    }
+
+    // Exchange aliases of two entries
+    if let Some(v) = args.methods.swap_alias {
+        actions::swap_alias(&db_fullpath, tablename, &v[0], &v[1], &matching);
+    }
+
+    // Add or remove additional alias
+    if let Some(v) = args.methods.add_alias {
+        let idx = match v[0].parse::<u32>() {
+            Ok(n) => n,
+            Err(_) => actions::exit_with_error(actions::ErrorKind::Usage, "Not an idx value"),
+        };
+        actions::add_alias(&db_fullpath, tablename, idx, &v[1]);
+    }
+    if let Some(v) = args.methods.del_alias {
+        let idx = match v[0].parse::<u32>() {
+            Ok(n) => n,
+            Err(_) => actions::exit_with_error(actions::ErrorKind::Usage, "Not an idx value"),
+        };
+        actions::del_alias(&db_fullpath, tablename, idx, &v[1]);
+    }
+
+    // Write entries to file
+    if let Some(file) = args.methods.export {
+        actions::export(&db_fullpath, tablename, &file);
+    }
+
+    // Add entries from file
+    if let Some(file) = args.methods.import {
+        actions::import(&db_fullpath, tablename, &file);
+    }
+
+    // Copy database
+    if let Some(dir) = args.methods.backup {
+        actions::backup(&db_fullpath, dir);
+    }
+
+    // Replace beginning of directories
+    if let Some(v) = args.methods.rewrite_prefix {
+        let old = Utf8PathBuf::from(&v[0]);
+        let new = Utf8PathBuf::from(&v[1]);
+        actions::rewrite_prefix(&db_fullpath, tablename, old, new, args.dry_run);
+    }
+
+    // Give entries with duplicate idx values new ones
+    if args.methods.fix_idx {
+        actions::fix_idx(&db_fullpath, tablename, args.dry_run);
+    }
+
+    // Report aliases used more than once
+    if args.methods.check_aliases {
+        actions::check_aliases(&db_fullpath, tablename);
+    }
+
+    // Print schema of database
+    if args.methods.dump_schema {
+        actions::dump_schema(&db_fullpath);
+    }
+
+    // Free unused pages of database
+    if args.methods.incremental_vacuum {
+        actions::incremental_vacuum(&db_fullpath);
     }
 
     // Find idx of directory
     if let Some(dir) = args.methods.query_path {
-        actions::find_directory(&db_fullpath, tablename, dir);
+        match (dir, args.stdin) {
+            (Some(dir), false) => actions::find_directory(
+                &db_fullpath,
+                tablename,
+                dir,
+                args.lexical,
+                args.strict,
+                args.ensure,
+                args.all,
+            ),
+            (None, true) => actions::find_directories(&db_fullpath, tablename, args.lexical),
+            _ => actions::exit_with_error(
+                actions::ErrorKind::Usage,
+                "Query needs either PATH or --stdin",
+            ),
+        }
+    }
+
+    // Find entries by part of their path
+    if let Some(part) = args.methods.find {
+        actions::find_containing(&db_fullpath, tablename, &part, args.idx_only);
+    }
+
+    // Depth of stack for shell prompts, silent without session
+    if args.methods.prompt_status {
+        let sessionid = if use_stack {
+            Some(sessionid.as_str())
+        } else {
+            None
+        };
+        actions::stack_prompt_status(&db_fullpath, sessionid, stack_expire);
+    }
+
+    // Number of entries, or of entries on stack
+    if let Some(what) = args.methods.count {
+        let sessionid = if what.as_deref() == Some("stack") {
+            if !use_stack {
+                actions::exit_with_error(
+                    actions::ErrorKind::Session,
+                    "Missing or wrong session-id!",
+                );
+            }
+            Some(sessionid.as_str())
+        } else {
+            None
+        };
+        actions::count(&db_fullpath, tablename, sessionid, stack_expire);
+    }
+
+    // Number of visits of an entry
+    if let Some(idx) = args.methods.visits {
+        actions::print_visits(&db_fullpath, tablename, idx);
+    }
+
+    // Report if stack commands can be used
+    if args.methods.verify_session {
+        actions::verify_session(&sessionid, use_stack);
     }
 
     // Stack operations
 
     if !use_stack {
-        eprintln!("Missing or wrong session-id!");
-        process::exit(1);
+        actions::exit_with_error(actions::ErrorKind::Session, "Missing or wrong session-id!");
     }
 
     // Print entries on stack
     if args.methods.list_stack {
-        actions::stack_list_dirs(&db_fullpath, &sessionid);
+        actions::stack_list_dirs(&db_fullpath, &sessionid, stack_expire);
+    }
+
+    // Print entry of stack at given depth
+    if let Some(depth) = args.methods.stack_at {
+        actions::stack_at(&db_fullpath, &sessionid, stack_expire, depth);
+    }
+
+    // Check if path is on stack
+    if let Some(dir) = args.methods.stack_contains {
+        actions::stack_contains(&db_fullpath, &sessionid, stack_expire, dir);
+    }
+
+    // Print commands restoring stack
+    if args.methods.export_stack_script {
+        actions::stack_export_script(&db_fullpath, &sessionid, stack_expire);
     }
 
     // Add work dir to stack
-    if args.methods.push {
-        let cur_dir = get_cwd();
-        let res = actions::stack_push(&db_fullpath, &sessionid, cur_dir);
+    if let Some(dir) = args.methods.push {
+        let dir = dir.unwrap_or_else(get_cwd);
+        let res = actions::stack_push(
+            &db_fullpath,
+            &sessionid,
+            stack_expire,
+            stack_max,
+            dir,
+            args.note.as_deref(),
+            args.force_push,
+        );
         if let Err(e) = res {
-            eprintln!("{e}");
+            actions::exit_with_error(actions::ErrorKind::Stack, &e);
         }
-        process::exit(1);
+        process::exit(actions::EXIT_OK);
+    }
+
+    // Print top of stack, keep it
+    if args.methods.peek {
+        actions::stack_peek(&db_fullpath, &sessionid, stack_expire);
     }
 
     // Change directory to top of stack, remove that entry
     if args.methods.pop {
-        actions::stack_pop(&db_fullpath, &sessionid);
+        if args.print_only {
+            actions::stack_print_top(&db_fullpath, &sessionid, stack_expire);
+        }
+        actions::stack_pop(&db_fullpath, &sessionid, stack_expire, args.dry_run);
+    }
+
+    // Pop stack down to directory of entry, chdir there
+    if let Some(entry) = args.methods.pop_to {
+        actions::stack_pop_to(
+            &db_fullpath,
+            tablename,
+            &sessionid,
+            stack_expire,
+            &entry,
+            &matching,
+        );
+    }
+
+    // Exchange two entries of stack
+    if let Some(v) = args.methods.stack_swap {
+        actions::stack_swap_depths(&db_fullpath, &sessionid, stack_expire, v[0], v[1]);
     }
 
     // Remove entry on top of stack
     if args.methods.drop {
-        actions::stack_drop(&db_fullpath, &sessionid);
+        actions::stack_drop(&db_fullpath, &sessionid, stack_expire, args.dry_run);
     }
 
     // Exchange top of stack with current work dir, chdir to former top of stack
     if args.methods.swap {
         let cur_dir = get_cwd();
-        actions::stack_swap(&db_fullpath, &sessionid, cur_dir);
+        actions::stack_swap(
+            &db_fullpath,
+            &sessionid,
+            stack_expire,
+            stack_max,
+            cur_dir,
+            args.dry_run,
+        );
+    }
+
+    // Remove all entries of session from stack
+    if args.methods.clear_stack {
+        actions::stack_clear(&db_fullpath, &sessionid, stack_expire);
+    }
+
+    // Add directories on stack to (main) table
+    if args.methods.stack_to_main {
+        actions::stack_to_main(&db_fullpath, tablename, &sessionid, stack_expire);
+    }
+
+    // Move bottom of stack to top, chdir there
+    if args.methods.cycle {
+        actions::stack_cycle(&db_fullpath, &sessionid, stack_expire);
     }
 } // main
 
@@ -158,13 +596,37 @@ fn get_cwd() -> Utf8PathBuf {
     let cwd = env::current_dir().unwrap();
     match Utf8PathBuf::from_path_buf(cwd) {
         Ok(pth) => pth,
-        Err(_) => {
-            println!("Current work directory appears to be no UTF-8 path");
-            process::exit(1);
-        }
+        Err(_) => actions::exit_with_error(
+            actions::ErrorKind::Path,
+            "Current work directory appears to be no UTF-8 path",
+        ),
     }
 } // get_cwd
 
+/// Returns directory of database if QCD_RS_DBPATH is unset. A database
+/// db_name already existing in home is kept, otherwise on Unix the
+/// directory qcd_rs in the XDG data directory is used (created if needed).
+fn default_db_dir(home: PathBuf, db_name: &str) -> PathBuf {
+    const XDGDATA_KEY: &str = "XDG_DATA_HOME";
+
+    if !cfg!(unix) || home.join(db_name).exists() {
+        return home;
+    }
+    // Relative paths are invalid according to the XDG specification
+    let data_home = match env::var(XDGDATA_KEY) {
+        Ok(val) if Path::new(&val).is_absolute() => PathBuf::from(val),
+        _ => home.join(".local").join("share"),
+    };
+    let dir = data_home.join("qcd_rs");
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        actions::exit_with_error(
+            actions::ErrorKind::General,
+            &format!("Could not create directory {}\n{e}", dir.display()),
+        );
+    }
+    dir
+} // default_db_dir
+
 mod options {
     use camino::Utf8PathBuf;
     use clap::{Args, ColorChoice, Parser};
@@ -173,27 +635,126 @@ mod options {
 "Environment variables
 =====================
   QCD_RS_DBNAME: Name of database. Default: '.qcd_rs.sqlite'
-  QCD_RS_DBPATH: Path to database. Default: home-directory
+  QCD_RS_DBPATH: Path to database. Default: $XDG_DATA_HOME/qcd_rs (or ~/.local/share/qcd_rs),
+                 home-directory on Windows or if database already exists there
+  QCD_RS_HOME: Replaces home-directory (QCD_RS_DBPATH takes precedence)
+  QCD_RS_DB_SEARCH_PATH: Further databases searched for entries (like PATH)
+  QCD_RS_PROMPT_GLYPH: Printed before stack depth by --prompt-status. Default: '\u{21e1}'
+  QCD_RS_ERROR_FORMAT: Set to 'json' for error objects on stderr
+  QCD_RS_MAX_CANDIDATES: Max. number of aliases listed if ambiguous. Default: 10
+  QCD_RS_STACK_EXPIRE_DAYS: Days after which stack entries expire, 0 disables. Default: 21
+  QCD_RS_STACK_MAX: Max. number of entries on stack, oldest ones are dropped, 0 disables pushing
+  QCD_RS_MATCH: Set to 'subsequence' to let e.g. 'wrk' match alias 'work'
+  QCD_RS_ALIAS_SEP: Separator of alias components, e.g. '.' lets 'proj' list 'proj.a', 'proj.b'
+  QCD_RS_HIDE_PATTERN: Regex, matching aliases or paths are omitted by -l
+  QCD_RS_LIST_FILL: Character padding aliases in -l, e.g. '.'. Default: space
+  QCD_RS_TIME_FORMAT: strftime-like format of times in -l --long. Default: relative times
+  QCD_RS_TIMEZONE: Timezone for QCD_RS_TIME_FORMAT: local, UTC or offset like +02:00
+  QCD_RS_NORMALIZE_SEPARATORS: If set, paths are stored with '/' as separator
+  QCD_RS_RESOLVER: Command asked for the path of unknown entries
+  QCD_RS_RESOLVER_ADD: If set, paths from QCD_RS_RESOLVER are added to database
+  QCD_RS_TOUCH_ON_ECHO: If set, -e counts as visit of the entry (see --sort frecency)
+  QCD_RS_ON_MISSING: Set to 'create' to add unknown ENTRY if it is a directory (qcd ENTRY)
+  QCD_RS_REQUIRE_DB: If set, a database in QCD_RS_DBPATH must exist (see --create-db)
+  QCD_RS_AUTO_VACUUM: If set when the database is created, it supports --incremental-vacuum
+
+Exit codes
+==========
+  0: Success. For navigation commands the path to change to was printed
+  1: Error
+  2: Usage error, missing or wrong session-id
+  3: Entry not found, stack empty
 
 
 Usage examples:
 Change directory
 ================
   qcd ENTRY [-n]                    Chdir to path with idx or alias ENTRY (w/o -n: adds work dir to stack)
+  qcd ENTRY --allow-missing [-n]    Chdir to path of ENTRY even if it does not exist (also: -e, --go, --menu)
+  qcd ENTRY SUBDIR [-n]             Chdir to subdirectory SUBDIR of path with idx or alias ENTRY
+  qcd ^SUBDIR [-n]                  Chdir to subdirectory SUBDIR of top of stack
   qcd -o                            (pop)  Chdir to top of stack, remove that entry from stack
+  qcd --print-only -o               Print top of stack like -o but keep it on stack
+  qcd --peek                        Print top of stack (e.g. for prompts), exit code 3 and message
+                                    on stderr if stack is empty
+  qcd --pop-to ENTRY                Remove entries from stack down to path of ENTRY, chdir there
+  qcd --cycle                       Move bottom of stack to top and chdir there
+  qcd --menu [-n]                   Select entry from list and chdir to its path
+  qcd --go ARG [-n]                 Chdir to entry ARG, else push directory ARG and chdir there
   
 Add or remove an entry
 ======================
   qcd -a PATH [-i IDX] [-s ALIAS]   Add PATH to database
+  qcd -a PATH -s ALIAS -s ALIAS2    Add PATH with alias ALIAS and additional alias ALIAS2
+  qcd -a PATH --lexical             Add PATH, only collapse '.' and '..' (no absolute path)
   qcd -p [-i IDX] [-s ALIAS]        Add current working directory to database
+  qcd -p --expire 14d               Add current working directory, remove it after 14 days
+  qcd -p --parent [N] [-s ALIAS]    Add directory N (default: 1) levels above working directory
+  qcd --bookmark ALIAS [-f]         Add current working directory with ALIAS
+  qcd -p --only-if-changed          Add current working directory unless it is already contained
+  qcd -a PATH --allow-duplicate     Add PATH even if it is already contained (refused by default)
   qcd -r ENTRY                      Remove row with idx or alias ENTRY
-  qcd -u                            (push) Add current working directory to (top of) stack
+  qcd -r ENTRY ENTRY2 ...           Remove several rows at once, report each of them
+  qcd -r ENTRY --dry-run            Print row which would be removed (also: -b, -x, -o, -d, -w)
+  qcd --rename ENTRY NEWALIAS       Set alias of entry with idx or alias ENTRY
+  qcd --set-path ENTRY NEWPATH      Set path of entry with idx or alias ENTRY (see --allow-missing)
+  qcd --swap-alias A B              Exchange aliases of entries with idx or alias A and B
+  qcd --promote IDX                 Move entry IDX to idx 1, entries before it move up by one
+  qcd --demote IDX                  Move entry IDX to highest idx, entries after it move down by one
+  qcd --move ENTRY IDX              Move entry ENTRY to IDX, entries in between are shifted if IDX is taken
+  qcd --compact                     Renumber entries to idx 1, 2, ... keeping their order
+  qcd --add-alias IDX NAME          Add NAME as additional alias of entry IDX
+  qcd --del-alias IDX NAME          Remove additional alias NAME of entry IDX
+  qcd --rewrite-prefix OLD NEW      Replace OLD by NEW in paths (see --dry-run)
+  qcd --fix-idx [--dry-run]         Give entries with duplicate idx new idx values
+  qcd --export FILE                 Write entries to FILE ('-': stdout) for --import
+  qcd --import FILE                 Add entries from lines 'idx<TAB>alias<TAB>path' of FILE
+  qcd --backup [DIR]                Copy database to DIR, file name gets timestamp
+  qcd --replace-db FILE             Replace database by copy of FILE, keep backup of old one
+  qcd --create-db                   Create database (required if QCD_RS_REQUIRE_DB is set)
+  qcd -u [PATH]                     (push) Add PATH or current working directory to (top of) stack
+  qcd -u [PATH] --force-push        Same as above, even if PATH is already on top of stack
+  qcd -u [PATH] --note TEXT         Same as above, TEXT is printed to stderr when popped
+  qcd --export-stack-script         Print 'qcd -u PATH' commands restoring the stack
+  qcd --stack-contains PATH         Exit code 0 if PATH is on stack, 1 otherwise
+  qcd --stack-at N                  Print entry at depth N of stack (0 = top)
+  qcd --stack-swap A B              Exchange entries at depths A and B of stack
+  qcd --verify-session              Report if QCD_RS_SESSIONID allows stack commands
+  qcd --prompt-status               Print e.g. '\u{21e1}3' for 3 entries on stack (for shell prompts)
+  qcd --count stack                 Print number of entries on stack
+  qcd --clear-stack                 Remove all entries from stack
+  qcd --stack-to-main               Add all paths on stack to database
   
 Queries
 =======
   qcd -l                            List all indexes, aliases and paths
-  qcd -q PATH                       Query index of PATH
+  qcd -l --git                      Same as above, shows git branch of repositories
+  qcd -l --status                   Same as above, shows if paths exist (see README)
+  qcd -l --long                     Same as above, shows additional aliases and expiry
+  qcd -l --relative-only            Only list relative paths (see --absolute-only)
+  qcd -l --tree                     List entries as tree of their paths
+  qcd -l --under ENTRY              List entries below path of ENTRY, relative to that path
+  qcd -l --existing-first           List existing paths before missing ones
+  qcd -l --jsonl | jq -c            List entries as JSON objects, one per line
+  qcd -l --format json (-J)         List entries as JSON array of objects
+  qcd -l --sort frecency            List often and recently visited entries first
+  qcd --count                       Print number of entries
+  qcd --visits IDX                  Print how often entry IDX was visited
+  qcd -q PATH                       Query index of PATH, -1 if not found
+  qcd -q PATH --strict              Query index of PATH, exit code 3 if not found
+  qcd -q PATH --ensure              Query index of PATH, add PATH if not found
+  qcd -q PATH --all                 Query indices of all entries with PATH, one per line
+  qcd -q --stdin < dirs.txt         Query index of each path read from stdin, one per line
+  qcd --find SUBSTR [--idx-only]    List entries (or indices) whose path contains SUBSTR
   ls `qcd -e 4`                     List directory contents of path with idx 4
+  qcd -e ENTRY --json               Print idx, alias and path of ENTRY as JSON object
+  qcd -e ENTRY --path-style wsl     Print path of ENTRY, e.g. C:\\proj as /mnt/c/proj
+  qcd_rs --resolve-all a b 3        Print paths of several entries, one per line
+  qcd_rs ENTRY --porcelain          Like chdir, but print 'idx<TAB>alias<TAB>path' for scripts
+  qcd --check-aliases               Report aliases used by several entries
+  qcd --dump-schema                 Print SQL schema of database
+  qcd --incremental-vacuum          Free unused pages of database (see QCD_RS_AUTO_VACUUM)
+  eval \"$(qcd_rs --export-env)\"     Define QCD_<ALIAS> variables for all aliases
 
 Alias matching
 ==============
@@ -209,6 +770,18 @@ second one while 'qcd pe' will match none.";
         #[command(flatten)]
         pub methods: Methods,
 
+        /// Subdirectory of ENTRY to change to
+        #[arg(value_name = "SUBDIR", requires = "entry")]
+        pub subdir: Option<Utf8PathBuf>,
+
+        /// Print 'idx<TAB>alias<TAB>directory' when changing to ENTRY
+        #[arg(long = "porcelain", requires = "entrygrp")]
+        pub porcelain: bool,
+
+        /// Accept path of entry even if it does not exist (e.g. removable media)
+        #[arg(long = "allow-missing", requires = "missinggrp")]
+        pub allow_missing: bool,
+
         /// Do not add current path to stack when changing directory
         #[arg(short = 'n', long = "no-push", requires = "chggrp")]
         pub no_push: bool,
@@ -217,71 +790,434 @@ second one while 'qcd pe' will match none.";
         #[arg(short = 'i', long = "idx", requires = "addgrp")]
         pub idx: Option<u32>,
 
-        /// Specify alias when adding path
+        /// Specify alias when adding path, repeat for additional aliases
         #[arg(short = 's', long = "alias", requires = "addgrp")]
-        pub alias: Option<String>,
+        pub alias: Vec<String>,
+
+        /// Remove entry after TTL, e.g. 12h, 14d, 2w
+        #[arg(long = "expire", value_name = "TTL", requires = "addgrp")]
+        pub expire: Option<String>,
+
+        /// Add the directory N levels (default: 1) above current work dir
+        #[arg(long = "parent", value_name = "N", requires = "currentgrp")]
+        pub parent: Option<Option<usize>>,
+
+        /// Show current git branch of listed directories
+        #[arg(long = "git", requires = "list_paths")]
+        pub git: bool,
+
+        /// Show if paths exist and git operations are in progress
+        #[arg(long = "status", requires = "list_paths")]
+        pub status: bool,
+
+        /// Also show additional aliases and expiry of listed entries
+        #[arg(long = "long", requires = "list_paths")]
+        pub long: bool,
+
+        /// List entries as JSON objects, one per line
+        #[arg(
+            long = "jsonl",
+            requires = "list_paths",
+            conflicts_with_all = ["git", "status", "long"]
+        )]
+        pub jsonl: bool,
+
+        /// Output format of listed entries: table (default) or json (single array)
+        #[arg(
+            long = "format",
+            value_name = "FORMAT",
+            value_parser = ["table", "json"],
+            requires = "list_paths",
+            conflicts_with_all = ["git", "status", "long", "jsonl", "tree"]
+        )]
+        pub format: Option<String>,
+
+        /// Short for --format json
+        #[arg(
+            short = 'J',
+            requires = "list_paths",
+            conflicts_with_all = ["git", "status", "long", "jsonl", "tree", "format"]
+        )]
+        pub json_list: bool,
+
+        /// Print entry as JSON object, errors as JSON object on stderr
+        #[arg(long = "json", requires = "echo")]
+        pub json: bool,
+
+        /// Print path of entry as posix (forward slashes), native or wsl (/mnt/c/...) path
+        #[arg(
+            long = "path-style",
+            value_name = "STYLE",
+            value_parser = ["posix", "native", "wsl"],
+            requires = "echo"
+        )]
+        pub path_style: Option<String>,
+
+        /// List existing paths before missing ones
+        #[arg(long = "existing-first", requires = "list_paths")]
+        pub existing_first: bool,
+
+        /// Order of listed entries: idx (default) or frecency (often and recently visited first)
+        #[arg(
+            long = "sort",
+            value_name = "ORDER",
+            value_parser = ["idx", "frecency"],
+            requires = "list_paths",
+            conflicts_with = "tree"
+        )]
+        pub sort: Option<String>,
+
+        /// List entries as tree of their paths
+        #[arg(
+            long = "tree",
+            requires = "list_paths",
+            conflicts_with_all = ["git", "status", "long", "jsonl", "under"]
+        )]
+        pub tree: bool,
+
+        /// Only list entries below path of ENTRY, relative to that path
+        #[arg(long = "under", value_name = "ENTRY", requires = "list_paths")]
+        pub under: Option<String>,
+
+        /// Only list entries with absolute path
+        #[arg(long = "absolute-only", requires = "list_paths")]
+        pub absolute_only: bool,
+
+        /// Only list entries with relative path
+        #[arg(
+            long = "relative-only",
+            requires = "list_paths",
+            conflicts_with = "absolute_only"
+        )]
+        pub relative_only: bool,
+
+        /// Also list entries matching QCD_RS_HIDE_PATTERN
+        #[arg(long = "show-hidden", requires = "list_paths")]
+        pub show_hidden: bool,
+
+        /// Add path even if it is already contained in database (--bookmark)
+        /// or its alias is numeric
+        #[arg(short = 'f', long = "force", requires = "forcegrp")]
+        pub force: bool,
+
+        /// Do nothing (exit 0) if path is already contained in database
+        #[arg(
+            long = "only-if-changed",
+            requires = "forcegrp",
+            conflicts_with = "force"
+        )]
+        pub only_if_changed: bool,
+
+        /// Add path even if it is already contained in database
+        #[arg(
+            long = "allow-duplicate",
+            requires = "addgrp",
+            conflicts_with = "only_if_changed"
+        )]
+        pub allow_duplicate: bool,
+
+        /// Report skipped paths of --only-if-changed
+        #[arg(long = "verbose")]
+        pub verbose: bool,
+
+        /// Only print what would be changed
+        #[arg(long = "dry-run", requires = "dryrungrp")]
+        pub dry_run: bool,
+
+        /// Only print top of stack, do not remove it
+        #[arg(long = "print-only", requires = "pop")]
+        pub print_only: bool,
+
+        /// Push even if path equals top of stack
+        #[arg(long = "force-push", requires = "pushgrp")]
+        pub force_push: bool,
+
+        /// Store TEXT with the pushed entry, printed to stderr on pop
+        #[arg(long = "note", value_name = "TEXT", requires = "pushgrp")]
+        pub note: Option<String>,
+
+        /// Exit with 3 instead of printing -1 if PATH is not found
+        #[arg(long = "strict", requires = "query_path")]
+        pub strict: bool,
+
+        /// Add PATH to database if it is not found, then print its index
+        #[arg(long = "ensure", requires = "query_path", conflicts_with = "strict")]
+        pub ensure: bool,
+
+        /// Only print indices of found entries
+        #[arg(long = "idx-only", requires = "findgrp")]
+        pub idx_only: bool,
+
+        /// Print index of every entry with PATH, one per line
+        #[arg(long = "all", requires = "query_path")]
+        pub all: bool,
+
+        /// Query paths read from stdin (one per line) instead of PATH
+        #[arg(
+            long = "stdin",
+            requires = "query_path",
+            conflicts_with_all = ["strict", "ensure"]
+        )]
+        pub stdin: bool,
+
+        /// Only collapse '.' and '..' of PATH, keep relative paths
+        #[arg(long = "lexical", requires = "lexgrp")]
+        pub lexical: bool,
     } // struct Arguments
 
     #[derive(Args, Debug)]
     #[group(required = true, multiple = false)]
     pub struct Methods {
         /// Index or alias of path
-        #[arg(group = "chggrp")]
+        #[arg(group = "chggrp", group = "missinggrp", group = "entrygrp")]
         pub entry: Option<String>,
 
+        /// Chdir to entry ARG, or to directory ARG which is pushed onto stack
+        #[arg(
+            long = "go",
+            value_name = "ARG",
+            group = "chggrp",
+            group = "missinggrp"
+        )]
+        pub go: Option<String>,
+
+        /// Select entry from a list and chdir to its path
+        #[arg(long = "menu", group = "chggrp", group = "missinggrp")]
+        pub menu: bool,
+
         /// List all path-names and id's
         #[arg(short = 'l', long = "list-paths")]
         pub list_paths: bool,
 
         /// Add PATH to database
-        #[arg(short = 'a', long = "add", value_name = "PATH", group = "addgrp")]
+        #[arg(
+            short = 'a',
+            long = "add",
+            value_name = "PATH",
+            group = "addgrp",
+            group = "lexgrp",
+            group = "forcegrp"
+        )]
         pub add: Option<Utf8PathBuf>,
 
         /// Add current work dir to database
-        #[arg(short = 'p', long = "add-current", group = "addgrp")]
+        #[arg(
+            short = 'p',
+            long = "add-current",
+            group = "addgrp",
+            group = "currentgrp",
+            group = "forcegrp"
+        )]
         pub add_current: bool,
 
-        /// Remove path with index or alias equal to ENTRY
-        #[arg(short = 'r', long = "remove", value_name = "ENTRY")]
-        pub remove: Option<String>,
+        /// Add current work dir to database with ALIAS
+        #[arg(long = "bookmark", value_name = "ALIAS", group = "forcegrp")]
+        pub bookmark: Option<String>,
+
+        /// Remove paths with index or alias equal to ENTRY (one or more)
+        #[arg(
+            short = 'r',
+            long = "remove",
+            value_name = "ENTRY",
+            num_args(1..),
+            group = "dryrungrp"
+        )]
+        pub remove: Option<Vec<String>>,
 
         /// Set alias for entry IDX
-        #[arg(short='b', long="set-alias",  value_names=["IDX", "ALIAS"], num_args(2))]
+        #[arg(short='b', long="set-alias",  value_names=["IDX", "ALIAS"], num_args(2), group = "dryrungrp")]
         pub new_alias: Option<Vec<String>>,
 
+        /// Move entry IDX to idx 1, entries with lower idx move up by one
+        #[arg(long = "promote", value_name = "IDX")]
+        pub promote: Option<u32>,
+
+        /// Move entry IDX to highest idx, entries with higher idx move down by one
+        #[arg(long = "demote", value_name = "IDX")]
+        pub demote: Option<u32>,
+
+        /// Move ENTRY (idx or alias) to IDX, entries in between are shifted if IDX is taken
+        #[arg(long = "move", value_names = ["ENTRY", "IDX"], num_args(2))]
+        pub move_to: Option<Vec<String>>,
+
+        /// Renumber entries to idx 1, 2, ... keeping their order
+        #[arg(long = "compact")]
+        pub compact: bool,
+
+        /// Set alias of ENTRY (idx or alias)
+        #[arg(long = "rename", value_names = ["ENTRY", "NEWALIAS"], num_args(2))]
+        pub rename: Option<Vec<String>>,
+
+        /// Set path of ENTRY (idx or alias)
+        #[arg(
+            long = "set-path",
+            value_names = ["ENTRY", "NEWPATH"],
+            num_args(2),
+            group = "missinggrp"
+        )]
+        pub set_path: Option<Vec<String>>,
+
+        /// Exchange aliases of entries with idx or alias A and B
+        #[arg(long = "swap-alias", value_names = ["A", "B"], num_args(2))]
+        pub swap_alias: Option<Vec<String>>,
+
+        /// Add an additional alias NAME to entry IDX
+        #[arg(long = "add-alias", value_names = ["IDX", "NAME"], num_args(2))]
+        pub add_alias: Option<Vec<String>>,
+
+        /// Remove additional alias NAME from entry IDX
+        #[arg(long = "del-alias", value_names = ["IDX", "NAME"], num_args(2))]
+        pub del_alias: Option<Vec<String>>,
+
         /// Change IDX
-        #[arg(short='x', long="set-index", value_names=["OLDIDX", "NEWIDX"], num_args(2))]
+        #[arg(short='x', long="set-index", value_names=["OLDIDX", "NEWIDX"], num_args(2), group = "dryrungrp")]
         pub new_idx: Option<Vec<u32>>,
 
+        /// Replace OLD by NEW in all paths starting with OLD
+        #[arg(long="rewrite-prefix", value_names=["OLD", "NEW"], num_args(2), group = "dryrungrp")]
+        pub rewrite_prefix: Option<Vec<String>>,
+
+        /// Give entries with duplicate index new (unique) indices
+        #[arg(long = "fix-idx", group = "dryrungrp")]
+        pub fix_idx: bool,
+
         /// List entries on stack (top to bottom)
         #[arg(short = 'c', long = "list-stack")]
         pub list_stack: bool,
 
-        /// Add current work dir to stack
-        #[arg(short = 'u', long = "push")]
-        pub push: bool,
+        /// Print number of entries, or of entries on stack with 'stack'
+        #[arg(
+            long = "count",
+            value_name = "WHAT",
+            value_parser = ["entries", "stack"]
+        )]
+        pub count: Option<Option<String>>,
+
+        /// Print number of visits of entry IDX
+        #[arg(long = "visits", value_name = "IDX")]
+        pub visits: Option<u32>,
+
+        /// Report if a valid session id (QCD_RS_SESSIONID) is present
+        #[arg(long = "verify-session")]
+        pub verify_session: bool,
+
+        /// Print number of entries on stack for shell prompts
+        #[arg(long = "prompt-status")]
+        pub prompt_status: bool,
+
+        /// Print entry at depth N of stack (0 = top)
+        #[arg(long = "stack-at", value_name = "N")]
+        pub stack_at: Option<usize>,
+
+        /// Exit with 0 if PATH is on stack, with 1 otherwise
+        #[arg(long = "stack-contains", value_name = "PATH")]
+        pub stack_contains: Option<Utf8PathBuf>,
+
+        /// Print commands restoring stack (bottom to top)
+        #[arg(long = "export-stack-script")]
+        pub export_stack_script: bool,
+
+        /// Add PATH (default: current work dir) to stack
+        #[arg(short = 'u', long = "push", value_name = "PATH", group = "pushgrp")]
+        pub push: Option<Option<Utf8PathBuf>>,
 
         /// Chdir to top of stack and remove path from stack
-        #[arg(short = 'o', long = "pop")]
+        #[arg(short = 'o', long = "pop", group = "dryrungrp")]
         pub pop: bool,
 
+        /// Print top of stack without removing it
+        #[arg(long = "peek")]
+        pub peek: bool,
+
+        /// Pop stack until path of ENTRY is popped and chdir there
+        #[arg(long = "pop-to", value_name = "ENTRY")]
+        pub pop_to: Option<String>,
+
+        /// Exchange entries at depths A and B of stack (0 = top)
+        #[arg(long = "stack-swap", value_names = ["A", "B"], num_args(2))]
+        pub stack_swap: Option<Vec<usize>>,
+
         /// Remove entry on top of stack
-        #[arg(short = 'd', long = "drop")]
+        #[arg(short = 'd', long = "drop", group = "dryrungrp")]
         pub drop: bool,
 
         /// Chdir to top of stack and exchange top of stack by current work dir
-        #[arg(short = 'w', long = "swap")]
+        #[arg(short = 'w', long = "swap", group = "dryrungrp")]
         pub swap: bool,
 
+        /// Move bottom of stack to top and chdir there
+        #[arg(long = "cycle")]
+        pub cycle: bool,
+
+        /// Remove all entries from stack
+        #[arg(long = "clear-stack")]
+        pub clear_stack: bool,
+
+        /// Add all paths on stack to database
+        #[arg(long = "stack-to-main")]
+        pub stack_to_main: bool,
+
         /// Query index of PATH. Returns -1 if path not in table.
-        #[arg(short = 'q', long = "query", value_name = "PATH")]
-        pub query_path: Option<Utf8PathBuf>,
+        #[arg(short = 'q', long = "query", value_name = "PATH", group = "lexgrp")]
+        pub query_path: Option<Option<Utf8PathBuf>>,
+
+        /// List entries whose path contains SUBSTR
+        #[arg(long = "find", value_name = "SUBSTR", group = "findgrp")]
+        pub find: Option<String>,
 
         /// Print path with index or alias equal to ENTRY
-        #[arg(short = 'e', long = "echo", value_name = "ENTRY")]
+        #[arg(short = 'e', long = "echo", value_name = "ENTRY", group = "missinggrp")]
         pub echo: Option<String>,
 
+        /// Print export statements for all aliases, names start with PREFIX
+        #[arg(long = "export-env", value_name = "PREFIX", num_args(0..=1),
+              default_missing_value = "QCD_")]
+        pub export_env: Option<String>,
+
+        /// Print path for each ENTRY, an empty line if ENTRY is not found
+        #[arg(long = "resolve-all", value_name = "ENTRY", num_args(1..))]
+        pub resolve_all: Option<Vec<String>>,
+
+        /// Write entries to FILE ('-' for stdout) as lines idx<TAB>alias<TAB>path
+        #[arg(long = "export", value_name = "FILE")]
+        pub export: Option<Utf8PathBuf>,
+
+        /// Add entries from FILE ('-' for stdin) with lines idx<TAB>alias<TAB>path
+        #[arg(long = "import", value_name = "FILE")]
+        pub import: Option<Utf8PathBuf>,
+
+        /// Copy database to DIR (default: next to database)
+        #[arg(long = "backup", value_name = "DIR")]
+        pub backup: Option<Option<Utf8PathBuf>>,
+
+        /// Replace database by a copy of FILE (old database gets backed up)
+        #[arg(long = "replace-db", value_name = "FILE")]
+        pub replace_db: Option<Utf8PathBuf>,
+
+        /// Report aliases used by several entries
+        #[arg(long = "check-aliases")]
+        pub check_aliases: bool,
+
+        /// Print SQL schema of database
+        #[arg(long = "dump-schema")]
+        pub dump_schema: bool,
+
+        /// Free unused pages of database (see QCD_RS_AUTO_VACUUM)
+        #[arg(long = "incremental-vacuum")]
+        pub incremental_vacuum: bool,
+
+        /// Create database if not existing
+        #[arg(long = "create-db")]
+        pub create_db: bool,
+
+        #[arg(long = "self-test", hide = true)]
+        pub self_test: bool,
+
         #[arg(long = "pid", hide = true)]
         pub pid: bool,
+
+        /// Print completion script for SHELL
+        #[arg(long = "completions", value_name = "SHELL", hide = true)]
+        pub completions: Option<clap_complete::Shell>,
     } // struct Methods
 } // mod options
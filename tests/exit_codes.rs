@@ -0,0 +1,1135 @@
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+const SESSIONID: &str = "194811104321123401118419";
+
+/// Directory holding a fresh database for test name.
+fn db_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("qcd_rs_exit_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs qcd_rs with args against the database in db_dir.
+fn qcd(db_dir: &PathBuf, args: &[&str], sessionid: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_qcd_rs"))
+        .args(args)
+        .env("QCD_RS_DBPATH", db_dir)
+        .env("QCD_RS_DBNAME", "test.sqlite")
+        .env("QCD_RS_SESSIONID", sessionid)
+        .env_remove("QCD_RS_ERROR_FORMAT")
+        .output()
+        .unwrap()
+}
+
+fn stdout(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stdout).trim_end().to_string()
+}
+
+#[test]
+fn success_is_zero() {
+    let dir = db_dir("success");
+    let target = dir.to_str().unwrap();
+
+    let out = qcd(&dir, &["-a", target, "-s", "tmp"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-l"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-e", "tmp"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), target);
+    let out = qcd(&dir, &["1"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), target);
+    let out = qcd(&dir, &["-o", "--print-only"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-o"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-r", "tmp"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["--help"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["--verify-session"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+} // success_is_zero
+
+#[test]
+fn error_is_one() {
+    let dir = db_dir("error");
+    let target = dir.to_str().unwrap();
+
+    let out = qcd(&dir, &["-a", target, "-i", "3"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-a", "/", "-i", "3"], SESSIONID);
+    assert_eq!(out.status.code(), Some(1));
+} // error_is_one
+
+#[test]
+fn usage_error_is_two() {
+    let dir = db_dir("usage");
+
+    let out = qcd(&dir, &[], SESSIONID);
+    assert_eq!(out.status.code(), Some(2));
+    let out = qcd(&dir, &["-x", "1"], SESSIONID);
+    assert_eq!(out.status.code(), Some(2));
+    let out = qcd(&dir, &["-b", "one", "alias"], SESSIONID);
+    assert_eq!(out.status.code(), Some(2));
+    let out = qcd(&dir, &["-o"], "");
+    assert_eq!(out.status.code(), Some(2));
+    let out = qcd(&dir, &["--verify-session"], "");
+    assert_eq!(out.status.code(), Some(2));
+    let out = qcd(&dir, &["--verify-session"], "1234");
+    assert_eq!(out.status.code(), Some(2));
+} // usage_error_is_two
+
+#[test]
+fn not_found_is_three() {
+    let dir = db_dir("notfound");
+
+    let out = qcd(&dir, &["42"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+    let out = qcd(&dir, &["-e", "nosuchalias"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+    let out = qcd(&dir, &["-r", "7"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+    let out = qcd(&dir, &["-o"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+    let out = qcd(&dir, &["-o", "--print-only"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+    let out = qcd(&dir, &["-q", "/", "--strict"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(stdout(&out), "");
+    let out = qcd(&dir, &["-q", "/"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "-1");
+} // not_found_is_three
+
+#[test]
+fn required_db_is_not_created() {
+    let dir = db_dir("requiredb");
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_qcd_rs"))
+            .args(args)
+            .env("QCD_RS_DBPATH", &dir)
+            .env("QCD_RS_DBNAME", "test.sqlite")
+            .env("QCD_RS_REQUIRE_DB", "1")
+            .output()
+            .unwrap()
+    };
+
+    let out = run(&["-l"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(!dir.join("test.sqlite").exists());
+    let out = run(&["--create-db"]);
+    assert_eq!(out.status.code(), Some(0));
+    let out = run(&["-l"]);
+    assert_eq!(out.status.code(), Some(0));
+} // required_db_is_not_created
+
+#[test]
+fn missing_directory_is_created() {
+    let dir = db_dir("onmissing");
+    let target = dir.to_str().unwrap();
+    let run = |on_missing: &str| {
+        Command::new(env!("CARGO_BIN_EXE_qcd_rs"))
+            .args(["-n", target])
+            .env("QCD_RS_DBPATH", &dir)
+            .env("QCD_RS_DBNAME", "test.sqlite")
+            .env("QCD_RS_ON_MISSING", on_missing)
+            .env_remove("QCD_RS_RESOLVER")
+            .output()
+            .unwrap()
+    };
+
+    let out = run("error");
+    assert_eq!(out.status.code(), Some(3));
+    let out = run("create");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), target);
+    let out = qcd(&dir, &["-q", target], SESSIONID);
+    assert_eq!(stdout(&out), "1");
+} // missing_directory_is_created
+
+#[test]
+fn go_to_entry_or_directory() {
+    let dir = db_dir("go");
+    let target = dir.to_str().unwrap();
+    let other = std::env::temp_dir();
+    let other = other.to_str().unwrap().trim_end_matches('/');
+
+    let out = qcd(&dir, &["-a", target, "-s", "here"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    // Entry
+    let out = qcd(&dir, &["-n", "--go", "here"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), target);
+    // Directory
+    let out = qcd(&dir, &["--go", other], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), other);
+    let out = qcd(&dir, &["-c"], SESSIONID);
+    assert_eq!(stdout(&out), other);
+    // Neither
+    let out = qcd(&dir, &["--go", "nosuchentry"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+} // go_to_entry_or_directory
+
+#[cfg(unix)]
+#[test]
+fn go_asks_resolver() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = db_dir("goresolver");
+    let resolver = dir.join("resolver.sh");
+    std::fs::write(&resolver, "#!/bin/sh\n[ \"$1\" = magic ] && echo /usr\n").unwrap();
+    std::fs::set_permissions(&resolver, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_qcd_rs"))
+            .args(args)
+            .env("QCD_RS_DBPATH", &dir)
+            .env("QCD_RS_DBNAME", "test.sqlite")
+            .env("QCD_RS_RESOLVER", &resolver)
+            .env_remove("QCD_RS_SESSIONID")
+            .output()
+            .unwrap()
+    };
+
+    let out = run(&["magic"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "/usr");
+    let out = run(&["--go", "magic"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "/usr");
+    let out = run(&["--go", "nosuchentry"]);
+    assert_eq!(out.status.code(), Some(3));
+} // go_asks_resolver
+
+#[test]
+fn subdirectory_of_entry() {
+    let dir = db_dir("subdir");
+    let target = dir.to_str().unwrap();
+    std::fs::create_dir_all(dir.join("src/lib")).unwrap();
+
+    let out = qcd(&dir, &["-a", target, "-s", "work"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-n", "work", "src/lib"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), format!("{target}/src/lib"));
+    let out = qcd(&dir, &["-n", "work", "nosuchdir"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+} // subdirectory_of_entry
+
+#[test]
+fn rename_by_idx_or_alias() {
+    let dir = db_dir("rename");
+    let target = dir.to_str().unwrap();
+
+    let out = qcd(&dir, &["-a", target, "-s", "first"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-a", "/", "-s", "fine"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    // By idx
+    let out = qcd(&dir, &["--rename", "1", "second"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-e", "second"], SESSIONID);
+    assert_eq!(stdout(&out), target);
+    // By (abbreviated) alias
+    let out = qcd(&dir, &["--rename", "sec", "third"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-e", "third"], SESSIONID);
+    assert_eq!(stdout(&out), target);
+    // Colliding alias, unknown entry
+    let out = qcd(&dir, &["--rename", "third", "fine"], SESSIONID);
+    assert_eq!(out.status.code(), Some(1));
+    let out = qcd(&dir, &["--rename", "nosuch", "x"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+} // rename_by_idx_or_alias
+
+#[test]
+fn home_replaced() {
+    let dir = db_dir("home");
+    let data = db_dir("home_data");
+
+    let run = |xdg_data: Option<&PathBuf>| {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_qcd_rs"));
+        cmd.arg("-l")
+            .env_remove("QCD_RS_DBPATH")
+            .env_remove("XDG_DATA_HOME")
+            .env("QCD_RS_HOME", &dir)
+            .env("QCD_RS_DBNAME", "test.sqlite");
+        if let Some(d) = xdg_data {
+            cmd.env("XDG_DATA_HOME", d);
+        }
+        cmd.output().unwrap()
+    };
+    let out = run(None);
+    assert_eq!(out.status.code(), Some(0));
+    if cfg!(unix) {
+        assert!(dir.join(".local/share/qcd_rs/test.sqlite").exists());
+        let out = run(Some(&data));
+        assert_eq!(out.status.code(), Some(0));
+        assert!(data.join("qcd_rs/test.sqlite").exists());
+        // Database in home is still used
+        std::fs::write(dir.join("test.sqlite"), "").unwrap();
+        let out = run(Some(&data));
+        assert_eq!(out.status.code(), Some(0));
+        assert!(!String::from_utf8_lossy(&out.stderr).contains("Creating"));
+    } else {
+        assert!(dir.join("test.sqlite").exists());
+    }
+} // home_replaced
+
+#[test]
+fn db_location_precedence() {
+    let home = db_dir("location");
+    let data = db_dir("location_data");
+    let explicit = db_dir("location_explicit");
+    let target = data.to_str().unwrap();
+
+    let run = |args: &[&str], xdg_data: Option<&str>, db_path: Option<&PathBuf>| {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_qcd_rs"));
+        cmd.args(args)
+            .current_dir(&home)
+            .env_remove("QCD_RS_DBPATH")
+            .env_remove("XDG_DATA_HOME")
+            .env("QCD_RS_HOME", &home)
+            .env("QCD_RS_DBNAME", "test.sqlite");
+        if let Some(d) = xdg_data {
+            cmd.env("XDG_DATA_HOME", d);
+        }
+        if let Some(p) = db_path {
+            cmd.env("QCD_RS_DBPATH", p);
+        }
+        cmd.output().unwrap()
+    };
+    if !cfg!(unix) {
+        return;
+    }
+
+    // Relative XDG_DATA_HOME is ignored
+    let out = run(&["-a", target], Some("relative"), None);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(home.join(".local/share/qcd_rs/test.sqlite").exists());
+    assert!(!home.join("relative").exists());
+
+    // Absolute XDG_DATA_HOME is used
+    let out = run(&["-l"], Some(target), None);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(data.join("qcd_rs/test.sqlite").exists());
+    assert_eq!(stdout(&out), "");
+
+    // Existing database in home takes precedence over XDG_DATA_HOME
+    std::fs::copy(
+        home.join(".local/share/qcd_rs/test.sqlite"),
+        home.join("test.sqlite"),
+    )
+    .unwrap();
+    let out = run(&["-e", "1"], Some(target), None);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), target);
+
+    // QCD_RS_DBPATH takes precedence over both
+    let out = run(&["-l"], Some(target), Some(&explicit));
+    assert_eq!(out.status.code(), Some(0));
+    assert!(explicit.join("test.sqlite").exists());
+    assert_eq!(stdout(&out), "");
+} // db_location_precedence
+
+#[test]
+fn menu_requires_terminal() {
+    let dir = db_dir("menu");
+
+    let out = qcd(&dir, &["-a", dir.to_str().unwrap()], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    // Test runs with piped stdin, so menu must not wait for a selection
+    let out = Command::new(env!("CARGO_BIN_EXE_qcd_rs"))
+        .arg("--menu")
+        .env("QCD_RS_DBPATH", &dir)
+        .env("QCD_RS_DBNAME", "test.sqlite")
+        .stdin(std::process::Stdio::piped())
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    assert!(stdout(&out).starts_with("ERROR: "));
+} // menu_requires_terminal
+
+#[test]
+fn search_path_databases() {
+    let dir = db_dir("searchpath");
+    let other = db_dir("searchpath_other");
+    let target = dir.to_str().unwrap();
+    let other_db = other.join("test.sqlite");
+
+    let out = qcd(&other, &["-a", target, "-s", "elsewhere"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-l"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_qcd_rs"))
+            .args(args)
+            .env("QCD_RS_DBPATH", &dir)
+            .env("QCD_RS_DBNAME", "test.sqlite")
+            .env("QCD_RS_DB_SEARCH_PATH", &other_db)
+            .output()
+            .unwrap()
+    };
+    let before = std::fs::read(&other_db).unwrap();
+    let out = run(&["-e", "else"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), target);
+    let out = run(&["-l"]);
+    assert!(stdout(&out).ends_with("(from test.sqlite)"));
+    let out = run(&["-r", "elsewhere"]);
+    assert_eq!(out.status.code(), Some(3));
+    // Databases of the search path are only read
+    assert_eq!(std::fs::read(&other_db).unwrap(), before);
+    assert_eq!(std::fs::read_dir(&other).unwrap().count(), 1);
+} // search_path_databases
+
+#[test]
+fn forced_push_duplicates_top() {
+    let dir = db_dir("forcepush");
+    let target = dir.to_str().unwrap();
+
+    let out = qcd(&dir, &["-u", target], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-u", target], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-c"], SESSIONID);
+    assert_eq!(stdout(&out), target);
+    let out = qcd(&dir, &["-u", target, "--force-push"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-c"], SESSIONID);
+    assert_eq!(stdout(&out), format!("{target}\n{target}"));
+} // forced_push_duplicates_top
+
+#[test]
+fn pop_to_entry() {
+    let dir = db_dir("popto");
+    let target = dir.to_str().unwrap();
+
+    let out = qcd(&dir, &["-a", target, "-s", "base"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-a", "/", "-s", "root"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    for path in [target, "/usr", "/etc"] {
+        let out = qcd(&dir, &["-u", path], SESSIONID);
+        assert_eq!(out.status.code(), Some(0));
+    }
+    // Target absent
+    let out = qcd(&dir, &["--pop-to", "root"], SESSIONID);
+    assert_eq!(out.status.code(), Some(1));
+    let out = qcd(&dir, &["-c"], SESSIONID);
+    assert_eq!(stdout(&out), format!("/etc\n/usr\n{target}"));
+    // Target present
+    let out = qcd(&dir, &["--pop-to", "base"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), target);
+    let out = qcd(&dir, &["-c"], SESSIONID);
+    assert_eq!(stdout(&out), "");
+} // pop_to_entry
+
+#[test]
+fn query_ensure_adds_path() {
+    let dir = db_dir("ensure");
+    let target = dir.to_str().unwrap();
+
+    let out = qcd(&dir, &["-a", "/", "-i", "4"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-q", target, "--ensure"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "5");
+    let out = qcd(&dir, &["-q", target, "--ensure"], SESSIONID);
+    assert_eq!(stdout(&out), "5");
+    let out = qcd(&dir, &["-q", target], SESSIONID);
+    assert_eq!(stdout(&out), "5");
+} // query_ensure_adds_path
+
+#[test]
+fn list_under_entry() {
+    let dir = db_dir("under");
+    let target = dir.to_str().unwrap();
+    let sub = format!("{target}/src");
+
+    let out = qcd(&dir, &["-a", target, "-s", "root"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-a", &sub, "-s", "src"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-a", "/", "-s", "other"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-l", "--under", "root"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "   1 root .\n   2 src  src");
+    let out = qcd(&dir, &["-l", "--under", "nosuch"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+} // list_under_entry
+
+#[test]
+fn list_as_json_array() {
+    let dir = db_dir("jsonarray");
+    let target = dir.to_str().unwrap();
+
+    let out = qcd(&dir, &["-l", "--format", "json"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "[]");
+    let out = qcd(&dir, &["-a", target, "-s", "my dir"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-a", "/"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-l", "-J"], SESSIONID);
+    assert_eq!(
+        stdout(&out),
+        format!(
+            "[{{\"idx\":1,\"alias\":\"my dir\",\"directory\":\"{target}\"}},\
+             {{\"idx\":2,\"alias\":\"\",\"directory\":\"/\"}}]"
+        )
+    );
+    let out = qcd(&dir, &["-l", "--format", "json", "--jsonl"], SESSIONID);
+    assert_eq!(out.status.code(), Some(2));
+} // list_as_json_array
+
+#[test]
+fn echo_as_json() {
+    let dir = db_dir("json");
+    let target = dir.to_str().unwrap();
+
+    let out = qcd(&dir, &["-a", target, "-s", "here"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-e", "here", "--json"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let expected = format!("{{\"idx\":1,\"alias\":\"here\",\"directory\":\"{target}\"}}");
+    assert_eq!(stdout(&out), expected);
+    let out = qcd(&dir, &["-e", "nosuch", "--json"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(stdout(&out), "");
+    let err = String::from_utf8_lossy(&out.stderr);
+    assert!(err.starts_with("{\"error\":"));
+    assert!(err.trim_end().ends_with("\"kind\":\"not_found\"}"));
+
+    let out = Command::new(env!("CARGO_BIN_EXE_qcd_rs"))
+        .args(["-e", "nosuch"])
+        .env("QCD_RS_DBPATH", &dir)
+        .env("QCD_RS_DBNAME", "test.sqlite")
+        .env("QCD_RS_ERROR_FORMAT", "json")
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(stdout(&out), "");
+    assert!(String::from_utf8_lossy(&out.stderr).starts_with("{\"error\":"));
+} // echo_as_json
+
+#[test]
+fn query_paths_from_stdin() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let dir = db_dir("stdin");
+    let target = dir.to_str().unwrap();
+
+    let out = qcd(&dir, &["-a", target], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let mut child = Command::new(env!("CARGO_BIN_EXE_qcd_rs"))
+        .args(["-q", "--stdin"])
+        .env("QCD_RS_DBPATH", &dir)
+        .env("QCD_RS_DBNAME", "test.sqlite")
+        .stdin(std::process::Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let input = format!("/\n\n{target}\n");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "-1\n\n1");
+
+    let out = qcd(&dir, &["-q"], SESSIONID);
+    assert_eq!(out.status.code(), Some(2));
+} // query_paths_from_stdin
+
+#[test]
+fn incremental_vacuum_needs_auto_vacuum() {
+    let dir = db_dir("vacuum");
+    let run = |auto_vacuum: &str| {
+        Command::new(env!("CARGO_BIN_EXE_qcd_rs"))
+            .arg("--incremental-vacuum")
+            .env("QCD_RS_DBPATH", &dir)
+            .env("QCD_RS_DBNAME", "test.sqlite")
+            .env("QCD_RS_AUTO_VACUUM", auto_vacuum)
+            .output()
+            .unwrap()
+    };
+
+    let out = run("1");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "0 pages freed");
+
+    let other = db_dir("vacuum_other");
+    let out = qcd(&other, &["--incremental-vacuum"], SESSIONID);
+    assert_eq!(out.status.code(), Some(1));
+} // incremental_vacuum_needs_auto_vacuum
+
+#[test]
+fn database_replaced() {
+    let dir = db_dir("replace");
+    let other = db_dir("replace_other");
+    let target = dir.to_str().unwrap();
+    let prepared = other.join("test.sqlite");
+
+    let out = qcd(&dir, &["-a", "/", "-s", "old"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&other, &["-a", target, "-s", "new"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+
+    let out = qcd(&dir, &["--replace-db", "/nosuchfile"], SESSIONID);
+    assert_eq!(out.status.code(), Some(1));
+    let out = qcd(
+        &dir,
+        &["--replace-db", prepared.to_str().unwrap()],
+        SESSIONID,
+    );
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-e", "new"], SESSIONID);
+    assert_eq!(stdout(&out), target);
+    let out = qcd(&dir, &["-e", "old"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+    // Backup of old database next to it
+    let files = std::fs::read_dir(&dir).unwrap().count();
+    assert_eq!(files, 2);
+} // database_replaced
+
+#[test]
+fn query_all_duplicates() {
+    let dir = db_dir("queryall");
+    let target = dir.to_str().unwrap();
+
+    let out = qcd(&dir, &["-q", target, "--all"], SESSIONID);
+    assert_eq!(stdout(&out), "-1");
+    for idx in ["2", "5"] {
+        let out = qcd(
+            &dir,
+            &["-a", target, "-i", idx, "--allow-duplicate"],
+            SESSIONID,
+        );
+        assert_eq!(out.status.code(), Some(0));
+    }
+    let out = qcd(&dir, &["-q", target, "--all"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "2\n5");
+} // query_all_duplicates
+
+#[test]
+fn numeric_alias_rejected() {
+    let dir = db_dir("numalias");
+    let target = dir.to_str().unwrap();
+
+    let out = qcd(&dir, &["-a", target, "-s", "42"], SESSIONID);
+    assert_eq!(out.status.code(), Some(2));
+    let out = qcd(&dir, &["-l"], SESSIONID);
+    assert_eq!(stdout(&out), "");
+    let out = qcd(&dir, &["-a", target, "-s", "42", "--force"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+} // numeric_alias_rejected
+
+#[test]
+fn relative_to_stack_top() {
+    let dir = db_dir("stackrel");
+    let target = dir.to_str().unwrap();
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+
+    let out = qcd(&dir, &["-n", "^sub"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+    let out = qcd(&dir, &["-u", target], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-n", "^sub"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), format!("{target}/sub"));
+    let out = qcd(&dir, &["-n", "^"], SESSIONID);
+    assert_eq!(stdout(&out), target);
+    let out = qcd(&dir, &["-n", "^nosuchdir"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+} // relative_to_stack_top
+
+#[test]
+fn add_only_if_changed() {
+    let dir = db_dir("onlyifchanged");
+    let target = dir.to_str().unwrap();
+
+    let out = qcd(&dir, &["-a", target, "--only-if-changed"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "Path added with index 1");
+    let out = qcd(&dir, &["-a", target, "--only-if-changed"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "");
+    let args = ["-a", target, "--only-if-changed", "--verbose"];
+    let out = qcd(&dir, &args, SESSIONID);
+    assert_eq!(stdout(&out), "Directory already registered with index 1");
+    let out = qcd(&dir, &["-l"], SESSIONID);
+    assert_eq!(stdout(&out).lines().count(), 1);
+    let out = qcd(&dir, &["-a", target, "--only-if-changed", "-f"], SESSIONID);
+    assert_eq!(out.status.code(), Some(2));
+} // add_only_if_changed
+
+#[test]
+fn add_refuses_duplicate() {
+    let dir = db_dir("duplicate");
+    let target = dir.to_str().unwrap();
+    let unclean = format!("{target}/./");
+
+    let out = qcd(&dir, &["-a", target, "-s", "proj"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-a", &unclean, "-s", "again"], SESSIONID);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        stdout(&out),
+        "ERROR: Directory already registered with index 1 (alias proj) (see --allow-duplicate)"
+    );
+    let out = qcd(&dir, &["-l"], SESSIONID);
+    assert_eq!(stdout(&out).lines().count(), 1);
+    let out = qcd(&dir, &["-a", target, "--allow-duplicate"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "Path added with index 2");
+    let out = qcd(
+        &dir,
+        &["-a", target, "--allow-duplicate", "--only-if-changed"],
+        SESSIONID,
+    );
+    assert_eq!(out.status.code(), Some(2));
+} // add_refuses_duplicate
+
+#[test]
+fn parallel_adds_get_distinct_idx() {
+    let dir = db_dir("paralleladd");
+    let out = qcd(&dir, &["-l"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+
+    let children: Vec<_> = (0..8)
+        .map(|i| {
+            let sub = dir.join(format!("d{i}"));
+            std::fs::create_dir_all(&sub).unwrap();
+            Command::new(env!("CARGO_BIN_EXE_qcd_rs"))
+                .arg("-a")
+                .arg(&sub)
+                .env("QCD_RS_DBPATH", &dir)
+                .env("QCD_RS_DBNAME", "test.sqlite")
+                .stdout(std::process::Stdio::null())
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+    for mut child in children {
+        assert_eq!(child.wait().unwrap().code(), Some(0));
+    }
+    let out = qcd(&dir, &["-l"], SESSIONID);
+    let idxs: Vec<String> = stdout(&out)
+        .lines()
+        .map(|l| l.split_whitespace().next().unwrap().to_string())
+        .collect();
+    assert_eq!(idxs, (1..=8).map(|i| i.to_string()).collect::<Vec<_>>());
+} // parallel_adds_get_distinct_idx
+
+#[test]
+fn compact_and_move() {
+    let dir = db_dir("compact");
+    let target = dir.to_str().unwrap();
+
+    for (path, idx) in [(target, "12"), ("/", "24"), ("/usr", "52")] {
+        let out = qcd(&dir, &["-a", path, "-i", idx], SESSIONID);
+        assert_eq!(out.status.code(), Some(0));
+    }
+    let out = qcd(&dir, &["--compact"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "3 entries changed");
+    for (idx, path) in [("1", target), ("2", "/"), ("3", "/usr")] {
+        assert_eq!(stdout(&qcd(&dir, &["-e", idx], SESSIONID)), path);
+    }
+
+    let out = qcd(&dir, &["--move", "3", "1"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    for (idx, path) in [("1", "/usr"), ("2", target), ("3", "/")] {
+        assert_eq!(stdout(&qcd(&dir, &["-e", idx], SESSIONID)), path);
+    }
+    let out = qcd(&dir, &["--move", "9", "1"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+    let out = qcd(&dir, &["--move", "1", "x"], SESSIONID);
+    assert_eq!(out.status.code(), Some(2));
+} // compact_and_move
+
+#[test]
+fn chdir_porcelain() {
+    let dir = db_dir("porcelain");
+    let target = dir.to_str().unwrap();
+
+    let out = qcd(&dir, &["-a", target, "-s", "proj", "-i", "4"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["proj"], SESSIONID);
+    assert_eq!(stdout(&out), target);
+    let out = qcd(&dir, &["proj", "--porcelain"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), format!("4\tproj\t{target}"));
+    let out = qcd(&dir, &["-u", "/usr"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["^", "--porcelain"], SESSIONID);
+    assert_eq!(stdout(&out), "\t\t/usr");
+    let out = qcd(&dir, &["-l", "--porcelain"], SESSIONID);
+    assert_eq!(out.status.code(), Some(2));
+} // chdir_porcelain
+
+#[test]
+fn peek_stack() {
+    let dir = db_dir("peek");
+
+    let out = qcd(&dir, &["--peek"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(stdout(&out), "");
+    assert!(!out.stderr.is_empty());
+    for path in ["/usr", "/etc"] {
+        let out = qcd(&dir, &["-u", path], SESSIONID);
+        assert_eq!(out.status.code(), Some(0));
+    }
+    for _ in 0..2 {
+        let out = qcd(&dir, &["--peek"], SESSIONID);
+        assert_eq!(out.status.code(), Some(0));
+        assert_eq!(stdout(&out), "/etc");
+    }
+    let out = qcd(&dir, &["-c"], SESSIONID);
+    assert_eq!(stdout(&out), "/etc\n/usr");
+    let out = qcd(&dir, &["--peek"], "");
+    assert_eq!(out.status.code(), Some(2));
+} // peek_stack
+
+#[test]
+fn note_printed_on_pop() {
+    let dir = db_dir("note");
+
+    let out = qcd(&dir, &["-u", "/usr", "--note", "build here"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-u", "/etc"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-o"], SESSIONID);
+    assert_eq!(stdout(&out), "/etc");
+    assert!(out.stderr.is_empty());
+    let out = qcd(&dir, &["-o"], SESSIONID);
+    assert_eq!(stdout(&out), "/usr");
+    assert_eq!(String::from_utf8_lossy(&out.stderr), "[note: build here]\n");
+    let out = qcd(&dir, &["-l", "--note", "x"], SESSIONID);
+    assert_eq!(out.status.code(), Some(2));
+} // note_printed_on_pop
+
+#[test]
+fn print_only_matches_pop() {
+    let dir = db_dir("printonly");
+
+    let out = qcd(&dir, &["-c"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let pop = qcd(&dir, &["-o"], SESSIONID);
+    assert_eq!(pop.status.code(), Some(3));
+    assert!(stdout(&pop).starts_with("ERROR: "));
+    for args in [["-o", "--print-only"], ["-o", "--dry-run"]] {
+        let out = qcd(&dir, &args, SESSIONID);
+        assert_eq!(out.status.code(), pop.status.code());
+        assert_eq!(stdout(&out), stdout(&pop));
+        assert_eq!(out.stderr, pop.stderr);
+    }
+} // print_only_matches_pop
+
+#[test]
+fn missing_directory() {
+    let dir = db_dir("missingdir");
+    let missing = format!("{}/gone", dir.to_str().unwrap());
+
+    let out = qcd(
+        &dir,
+        &["-a", &missing, "-s", "gone", "--lexical"],
+        SESSIONID,
+    );
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-n", "gone"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+    let out = qcd(&dir, &["-e", "gone"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+    let out = qcd(&dir, &["-n", "gone", "--allow-missing"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), missing);
+    let out = qcd(&dir, &["-e", "gone", "--allow-missing"], SESSIONID);
+    assert_eq!(stdout(&out), missing);
+} // missing_directory
+
+#[test]
+fn count_entries() {
+    let dir = db_dir("count");
+    let target = dir.to_str().unwrap();
+
+    let out = qcd(&dir, &["--count"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "0");
+    let out = qcd(&dir, &["-a", target], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["--count", "entries"], SESSIONID);
+    assert_eq!(stdout(&out), "1");
+    let out = qcd(&dir, &["--count", "stack"], SESSIONID);
+    assert_eq!(stdout(&out), "0");
+    let out = qcd(&dir, &["-u", target], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["--count", "stack"], SESSIONID);
+    assert_eq!(stdout(&out), "1");
+    let out = qcd(&dir, &["--count", "stack"], "");
+    assert_eq!(out.status.code(), Some(2));
+} // count_entries
+
+#[test]
+fn visits_of_entry() {
+    let dir = db_dir("visits");
+    let target = dir.to_str().unwrap();
+
+    let out = qcd(&dir, &["-a", target], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["--visits", "1"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "0");
+    for _ in 0..2 {
+        let out = qcd(&dir, &["1"], SESSIONID);
+        assert_eq!(out.status.code(), Some(0));
+    }
+    let out = qcd(&dir, &["--visits", "1"], SESSIONID);
+    assert_eq!(stdout(&out), "2");
+    let out = qcd(&dir, &["--visits", "2"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+
+    // Echo only counts with QCD_RS_TOUCH_ON_ECHO
+    let out = qcd(&dir, &["-e", "1"], SESSIONID);
+    assert_eq!(stdout(&out), target);
+    let out = qcd(&dir, &["--visits", "1"], SESSIONID);
+    assert_eq!(stdout(&out), "2");
+    let out = Command::new(env!("CARGO_BIN_EXE_qcd_rs"))
+        .args(["-e", "1"])
+        .env("QCD_RS_DBPATH", &dir)
+        .env("QCD_RS_DBNAME", "test.sqlite")
+        .env("QCD_RS_TOUCH_ON_ECHO", "1")
+        .output()
+        .unwrap();
+    assert_eq!(stdout(&out), target);
+    let out = qcd(&dir, &["--visits", "1"], SESSIONID);
+    assert_eq!(stdout(&out), "3");
+} // visits_of_entry
+
+#[test]
+fn completions_without_database() {
+    let dir = db_dir("completions");
+
+    let out = qcd(&dir, &["--completions", "bash"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(stdout(&out).contains("qcd"));
+    assert!(!dir.join("test.sqlite").exists());
+    let out = qcd(&dir, &["--completions", "tcsh"], SESSIONID);
+    assert_eq!(out.status.code(), Some(2));
+} // completions_without_database
+
+#[test]
+fn dry_run_keeps_database() {
+    let dir = db_dir("dryrun");
+    let target = dir.to_str().unwrap();
+
+    let out = qcd(&dir, &["-a", target, "-s", "proj"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-r", "proj", "--dry-run"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), format!("   1 proj {target} would be removed"));
+    let out = qcd(&dir, &["-x", "1", "5", "--dry-run"], SESSIONID);
+    assert_eq!(stdout(&out), format!("   1 proj {target} -> idx 5"));
+    let out = qcd(&dir, &["-e", "1"], SESSIONID);
+    assert_eq!(stdout(&out), target);
+
+    let out = qcd(&dir, &["-u", target], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-o", "--dry-run"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), target);
+    let out = qcd(&dir, &["-d", "--dry-run"], SESSIONID);
+    assert_eq!(stdout(&out), format!("{target} would be dropped"));
+    let out = qcd(&dir, &["-c"], SESSIONID);
+    assert_eq!(stdout(&out), target);
+} // dry_run_keeps_database
+
+#[test]
+fn remove_several_entries() {
+    let dir = db_dir("removeseveral");
+    let target = dir.to_str().unwrap();
+
+    for alias in ["a", "b", "c"] {
+        let args = ["-a", target, "-s", alias, "--allow-duplicate"];
+        let out = qcd(&dir, &args, SESSIONID);
+        assert_eq!(out.status.code(), Some(0));
+    }
+    let out = qcd(&dir, &["-r", "a", "nosuch", "3"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(
+        stdout(&out),
+        format!("   1 a {target} removed\n   3 c {target} removed\n2 of 3 entries removed")
+    );
+    let out = qcd(&dir, &["-l"], SESSIONID);
+    assert_eq!(stdout(&out), format!("   2 b {target}"));
+    let out = qcd(&dir, &["-r", "b", "2"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["--count"], SESSIONID);
+    assert_eq!(stdout(&out), "0");
+} // remove_several_entries
+
+#[test]
+fn export_import_round_trip() {
+    let dir = db_dir("export");
+    let other = db_dir("import");
+    let target = dir.to_str().unwrap();
+    let file = dir.join("entries.tsv");
+    let file = file.to_str().unwrap();
+
+    let out = qcd(&dir, &["-a", target, "-s", "proj", "-i", "3"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["-a", "/"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["--export", "-"], SESSIONID);
+    assert_eq!(stdout(&out), format!("3\tproj\t{target}\n4\t\t/"));
+    let out = qcd(&dir, &["--export", file], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+
+    let out = qcd(&other, &["--import", file], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "2 entries added, 0 skipped");
+    let out = qcd(&other, &["--import", file], SESSIONID);
+    assert_eq!(stdout(&out), "0 entries added, 2 skipped");
+    let listed = stdout(&qcd(&other, &["-l"], SESSIONID));
+    assert_eq!(listed, stdout(&qcd(&dir, &["-l"], SESSIONID)));
+} // export_import_round_trip
+
+#[test]
+fn verify_session_reports() {
+    let dir = db_dir("verify");
+
+    let out = qcd(&dir, &["--verify-session"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(stdout(&out).contains("is valid (24 characters)"));
+    // At least 23 characters are required
+    let out = qcd(&dir, &["--verify-session"], &SESSIONID[..23]);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["--verify-session"], &SESSIONID[..22]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(stdout(&out).contains("is too short (22 characters)"));
+    let out = qcd(&dir, &["--verify-session"], "");
+    assert_eq!(out.status.code(), Some(2));
+    assert!(stdout(&out).starts_with("QCD_RS_SESSIONID is not set"));
+} // verify_session_reports
+
+#[test]
+fn stack_added_to_main() {
+    let dir = db_dir("stacktomain");
+
+    let out = qcd(&dir, &["-a", "/etc"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    for path in ["/usr", "/etc", "/tmp"] {
+        let out = qcd(&dir, &["-u", path], SESSIONID);
+        assert_eq!(out.status.code(), Some(0));
+    }
+    let out = qcd(&dir, &["--stack-to-main"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "2 paths added");
+    // Oldest entry first, /etc is kept at its idx
+    for (idx, path) in [("1", "/etc"), ("2", "/usr"), ("3", "/tmp")] {
+        let out = qcd(&dir, &["-e", idx], SESSIONID);
+        assert_eq!(stdout(&out), path);
+    }
+    let out = qcd(&dir, &["--stack-to-main"], SESSIONID);
+    assert_eq!(stdout(&out), "0 paths added");
+} // stack_added_to_main
+
+#[test]
+fn stack_entry_at_depth() {
+    let dir = db_dir("stackat");
+
+    for path in ["/usr", "/etc", "/tmp"] {
+        let out = qcd(&dir, &["-u", path], SESSIONID);
+        assert_eq!(out.status.code(), Some(0));
+    }
+    let out = qcd(&dir, &["--stack-at", "0"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "/tmp");
+    let out = qcd(&dir, &["--stack-at", "2"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "/usr");
+    let out = qcd(&dir, &["--stack-at", "3"], SESSIONID);
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(stdout(&out), "ERROR: No entry at depth 3, stack holds 3");
+} // stack_entry_at_depth
+
+#[test]
+fn stack_contains_path() {
+    let dir = db_dir("stackcontains");
+
+    let out = qcd(&dir, &["-u", "/usr"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["--stack-contains", "/usr"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "");
+    // Path is cleaned before comparison
+    let out = qcd(&dir, &["--stack-contains", "/usr/bin/../"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    let out = qcd(&dir, &["--stack-contains", "/etc"], SESSIONID);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(stdout(&out), "");
+    let out = qcd(
+        &dir,
+        &["--stack-contains", "/usr"],
+        &SESSIONID.replace('1', "2"),
+    );
+    assert_eq!(out.status.code(), Some(1));
+} // stack_contains_path
+
+#[test]
+fn stack_export_script_restores_order() {
+    let dir = db_dir("exportstack");
+    let spaced = dir.join("my dir");
+    std::fs::create_dir_all(&spaced).unwrap();
+    let spaced = spaced.to_str().unwrap();
+
+    let out = qcd(&dir, &["--export-stack-script"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "");
+    for path in ["/usr", spaced, "/etc"] {
+        let out = qcd(&dir, &["-u", path], SESSIONID);
+        assert_eq!(out.status.code(), Some(0));
+    }
+    let out = qcd(&dir, &["--export-stack-script"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(
+        stdout(&out),
+        format!("qcd -u /usr\nqcd -u '{spaced}'\nqcd -u /etc")
+    );
+} // stack_export_script_restores_order
+
+#[test]
+fn prompt_status_of_stack() {
+    let dir = db_dir("promptstatus");
+
+    let out = qcd(&dir, &["--prompt-status"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "");
+    for path in ["/usr", "/etc"] {
+        let out = qcd(&dir, &["-u", path], SESSIONID);
+        assert_eq!(out.status.code(), Some(0));
+    }
+    let out = qcd(&dir, &["--prompt-status"], SESSIONID);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "\u{21e1}2");
+    // Silent without (valid) session
+    for sessionid in ["", "1234"] {
+        let out = qcd(&dir, &["--prompt-status"], sessionid);
+        assert_eq!(out.status.code(), Some(0));
+        assert_eq!(stdout(&out), "");
+        assert!(out.stderr.is_empty());
+    }
+} // prompt_status_of_stack